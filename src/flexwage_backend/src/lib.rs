@@ -1,9 +1,21 @@
-use candid::{CandidType, Deserialize, Principal};
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs, TransformContext,
+};
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument,
+    SignWithEcdsaArgument,
+};
 use ic_cdk::{api, query, update};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 
 // Type aliases
 type Memory = VirtualMemory<DefaultMemoryImpl>;
@@ -11,6 +23,12 @@ type UserId = String;
 type ShiftId = String;
 type WorkHistoryId = String;
 type RatingId = String;
+type AdvanceId = String;
+type EventId = String;
+// Inbox index key: (recipient, created_at newest first, notification id)
+type NotificationKey = (UserId, Reverse<u64>, String);
+// Labor rule findings for one worker, or for the shift alone when None
+type WorkerFindings = (Option<UserId>, Vec<ComplianceFinding>);
 
 // User Types
 #[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
@@ -19,6 +37,13 @@ pub enum UserType {
     Business,
 }
 
+// WGS 84 coordinates in decimal degrees
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
 // User Profile
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct UserProfile {
@@ -45,6 +70,8 @@ pub struct WorkerProfile {
     pub total_shifts_completed: u64,
     pub average_rating: Option<f32>,
     pub is_verified: bool,
+    pub home_area: Option<GeoPoint>, // centre of the area the worker looks for shifts in
+    pub birth_date: Option<String>, // "YYYY-MM-DD", used for minor-worker rules
 }
 
 // Business Profile
@@ -57,6 +84,7 @@ pub struct BusinessProfile {
     pub address: Option<String>,
     pub description: Option<String>,
     pub is_verified: bool,
+    pub coordinates: Option<GeoPoint>, // of the address; new shifts default to it
 }
 
 // Shift Status
@@ -69,6 +97,104 @@ pub enum ShiftStatus {
     InProgress,
     Completed,
     Cancelled,
+    Expired,
+}
+
+// Allowed shift lifecycle moves. Every status change must go through
+// `transition_shift`, which rejects anything not listed here.
+const SHIFT_TRANSITIONS: &[(ShiftStatus, ShiftStatus)] = &[
+    (ShiftStatus::Draft, ShiftStatus::Open),
+    (ShiftStatus::Draft, ShiftStatus::Cancelled),
+    (ShiftStatus::Open, ShiftStatus::Claimed),
+    (ShiftStatus::Open, ShiftStatus::Approved),
+    (ShiftStatus::Open, ShiftStatus::Cancelled),
+    (ShiftStatus::Claimed, ShiftStatus::Open),
+    (ShiftStatus::Claimed, ShiftStatus::Approved),
+    (ShiftStatus::Claimed, ShiftStatus::Cancelled),
+    (ShiftStatus::Approved, ShiftStatus::Open),
+    (ShiftStatus::Approved, ShiftStatus::InProgress),
+    (ShiftStatus::Approved, ShiftStatus::Cancelled),
+    // Partially staffed shifts still start with the seats they have
+    (ShiftStatus::Open, ShiftStatus::InProgress),
+    (ShiftStatus::Claimed, ShiftStatus::InProgress),
+    (ShiftStatus::InProgress, ShiftStatus::Completed),
+    // Every seat a no-show; refused once anyone has checked in
+    (ShiftStatus::InProgress, ShiftStatus::Cancelled),
+    // Unfilled shifts whose start has passed, set by the shift sweep
+    (ShiftStatus::Open, ShiftStatus::Expired),
+    (ShiftStatus::Claimed, ShiftStatus::Expired),
+];
+
+// Shift Transition Errors
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShiftTransitionError {
+    InvalidTransition { from: ShiftStatus, to: ShiftStatus },
+    WorkerNotAssigned { to: ShiftStatus },
+    WorkStarted,
+}
+
+impl std::fmt::Display for ShiftTransitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShiftTransitionError::InvalidTransition { from, to } => {
+                write!(f, "Invalid shift transition from {:?} to {:?}", from, to)
+            }
+            ShiftTransitionError::WorkerNotAssigned { to } => {
+                write!(f, "Cannot move shift to {:?} without an assigned worker", to)
+            }
+            ShiftTransitionError::WorkStarted => {
+                write!(f, "Cannot cancel a shift once a worker has checked in; complete it instead")
+            }
+        }
+    }
+}
+
+impl From<ShiftTransitionError> for String {
+    fn from(error: ShiftTransitionError) -> Self {
+        error.to_string()
+    }
+}
+
+// Money in integer minor units (e.g. cents) of an ISO 4217 currency
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Money {
+    pub amount_minor: u64,
+    pub currency: String,
+}
+
+impl Money {
+    fn from_major(amount: f64, currency: &str) -> Money {
+        let scale = 10f64.powi(currency_exponent(currency) as i32);
+        Money {
+            amount_minor: (amount * scale).round().max(0.0) as u64,
+            currency: currency.to_string(),
+        }
+    }
+    
+    // Treating self as an hourly rate, the pay for `duration_ms`, rounded to
+    // the nearest minor unit
+    fn for_duration_ms(&self, duration_ms: i64) -> Money {
+        let duration_ms = duration_ms.max(0) as u128;
+        let amount = (self.amount_minor as u128 * duration_ms + 1_800_000) / 3_600_000;
+        Money {
+            amount_minor: amount.min(u64::MAX as u128) as u64,
+            currency: self.currency.clone(),
+        }
+    }
+}
+
+// Minor-unit exponent per ISO 4217; two decimals unless listed
+fn currency_exponent(currency: &str) -> u32 {
+    match currency {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX"
+        | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+fn is_valid_currency(currency: &str) -> bool {
+    currency.len() == 3 && currency.chars().all(|c| c.is_ascii_uppercase())
 }
 
 // Shift
@@ -76,22 +202,47 @@ pub enum ShiftStatus {
 pub struct Shift {
     pub id: ShiftId,
     pub business_id: UserId,
+    pub event_id: Option<EventId>, // fixed when the shift is created
     pub role: String,
-    pub date: String,
-    pub start_time: String,
-    pub end_time: String,
-    pub pay_rate: f32,
+    pub starts_at: i64, // ms since the epoch, UTC
+    pub ends_at: i64,
+    pub utc_offset_minutes: i16, // local wall-clock offset at the shift location
+    pub time_zone: Option<String>, // IANA name, informational only
+    pub pay_rate: Money, // per hour
     pub location: String,
+    pub coordinates: Option<GeoPoint>,
+    pub jurisdiction: Option<String>, // selects the labor rule set, e.g. "US-CA"
     pub description: Option<String>,
     pub requirements: Vec<String>,
     pub status: ShiftStatus,
-    pub assigned_worker: Option<UserId>,
+    pub headcount: u32, // workers needed; Approved once every seat is filled
+    pub seats: Vec<ShiftSeat>,
     pub applicants: Vec<UserId>,
     pub is_urgent: bool,
     pub created_at: i64,
     pub updated_at: i64,
 }
 
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum SeatStatus {
+    Assigned,
+    CheckedIn,
+    Completed, // checked out, work history recorded
+}
+
+// One approved worker on a shift. Check-in, check-out, work history and
+// escrow are all tracked per seat.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ShiftSeat {
+    pub worker_id: UserId,
+    pub status: SeatStatus,
+    pub assigned_at: i64,
+    pub checked_in_at: Option<i64>,
+    pub checked_out_at: Option<i64>,
+    pub checkout_overdue_at: Option<i64>, // set by the shift sweep
+    pub work_history_id: Option<WorkHistoryId>,
+}
+
 // Work History Entry
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct WorkHistory {
@@ -102,11 +253,24 @@ pub struct WorkHistory {
     pub role: String,
     pub date_worked: String,
     pub hours_worked: f32,
-    pub pay_earned: f32,
+    pub minutes_worked: Option<u32>, // exact billable time; None on entries hashed before it existed
+    pub pay_earned: Money,
     pub business_name: String,
     pub location: String,
     pub completed_at: i64,
     pub verification_hash: String,
+    pub confirmed_at: Option<i64>,
+    pub signature: Option<String>,
+    pub settled_at: Option<i64>,
+}
+
+impl WorkHistory {
+    // Whole minutes worked; entries from before minutes_worked round their hours
+    fn worked_minutes(&self) -> u64 {
+        self.minutes_worked
+            .map(u64::from)
+            .unwrap_or_else(|| (self.hours_worked as f64 * 60.0).round().max(0.0) as u64)
+    }
 }
 
 // Rating Entry
@@ -123,6 +287,7 @@ pub struct Rating {
     pub date_worked: String,
     pub created_at: i64,
     pub verification_hash: String,
+    pub signature: Option<String>,
 }
 
 // DID Document
@@ -137,6 +302,112 @@ pub struct DIDDocument {
     pub created_at: i64,
     pub updated_at: i64,
     pub signature: String,
+    pub signed_digest: Option<String>, // hex SHA-256 of the canonical form `signature` covers
+}
+
+// Threshold key the canister signs DID documents and credentials with
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SigningKey {
+    pub canister_id: Principal,
+    pub curve: String,
+    pub key_name: String,
+    pub derivation_path: Vec<String>,
+    pub public_key: String,
+}
+
+// Portable DID export: the signed document plus every record it references
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DIDExport {
+    pub document: DIDDocument,
+    pub work_history: Vec<WorkHistory>,
+    pub ratings: Vec<Rating>,
+    pub signing_key: Option<SigningKey>,
+}
+
+// Selective disclosure request: explicit records and/or an aggregate claim,
+// bound to the verifier's nonce and an expiry
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PresentationRequest {
+    pub work_history_ids: Vec<WorkHistoryId>,
+    pub rating_ids: Vec<RatingId>,
+    pub aggregate: Option<AggregateCriteria>,
+    pub verifier_nonce: String,
+    pub expires_at: i64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AggregateCriteria {
+    pub business_type: Option<String>,
+    pub role: Option<String>,
+    pub completed_after: Option<i64>,
+    pub completed_before: Option<i64>,
+    pub min_average_rating: Option<f32>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Presentation {
+    pub id: String,
+    pub holder: String,
+    pub verifier_nonce: String,
+    pub expires_at: i64,
+    pub created_at: i64,
+    pub digest: String,
+    pub signature: String,
+    pub document: String, // JSON-LD Verifiable Presentation including its proof
+}
+
+// What the canister keeps to answer verify_presentation
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PresentationRecord {
+    pub id: String,
+    pub worker_id: UserId,
+    pub verifier_nonce: String,
+    pub expires_at: i64,
+    pub created_at: i64,
+    pub digest: String,
+    pub signature: String,
+}
+
+// Revocation Registry
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum CredentialKind {
+    WorkHistory,
+    Rating,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Revocation {
+    pub reason: String,
+    pub revoked_by: Principal,
+    pub revoked_at: i64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CredentialStatus {
+    pub credential_id: String,
+    pub kind: CredentialKind,
+    pub record_id: String,
+    pub worker_id: UserId,
+    pub issuer_business_id: UserId,
+    pub status_index: u64,
+    pub revocation: Option<Revocation>,
+}
+
+// Bit i (most significant bit first) is set when the credential with
+// status_index i has been revoked
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RevocationStatusList {
+    pub length: u64,
+    pub bitstring: Vec<u8>,
+    pub updated_at: i64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PresentationVerification {
+    pub valid: bool,
+    pub holder: Option<String>,
+    pub expires_at: Option<i64>,
+    pub reason: Option<String>,
 }
 
 // Shift Application
@@ -149,7 +420,7 @@ pub struct ShiftApplication {
     pub status: ApplicationStatus,
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
 pub enum ApplicationStatus {
     Pending,
     Approved,
@@ -157,7 +428,7 @@ pub enum ApplicationStatus {
 }
 
 // Notification Types
-#[derive(CandidType, Deserialize, Clone, Debug)]
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
 pub enum NotificationType {
     ShiftPosted,
     ShiftClaimed,
@@ -166,6 +437,9 @@ pub enum NotificationType {
     ShiftCompleted,
     ShiftCancelled,
     PaymentProcessed,
+    ShiftExpired,
+    ShiftStarted,
+    CheckOutOverdue,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -178,6 +452,744 @@ pub struct Notification {
     pub related_shift_id: Option<ShiftId>,
     pub is_read: bool,
     pub created_at: i64,
+    // Routing from the recipient's preferences at creation time
+    pub channel: Option<NotificationChannel>,
+    pub deliver_after: Option<i64>, // outbound delivery held back by quiet hours
+    pub delivered_at: Option<i64>,
+}
+
+// Labor Rules
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum RuleSeverity {
+    Block, // the action is rejected
+    Warn,  // the action goes ahead and the finding is recorded
+}
+
+// Durations are in minutes; times of day are local minutes after midnight
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum LaborRule {
+    MaxDailyWork { minutes: u32 },
+    MaxWeeklyWork { minutes: u32 }, // over the seven local days ending on the shift's
+    MinRest { minutes: u32 },       // between the end of one shift and the start of the next
+    MinPayRate { rate: Money },
+    MinorWorker {
+        adult_age: u8,
+        max_daily_minutes: u32,
+        earliest_start_minute: u16,
+        latest_end_minute: u16,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LaborRuleEntry {
+    pub rule: LaborRule,
+    pub severity: RuleSeverity,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LaborRuleSet {
+    pub jurisdiction: String,
+    pub rules: Vec<LaborRuleEntry>,
+    pub updated_at: i64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ComplianceFinding {
+    pub rule: String,
+    pub severity: RuleSeverity,
+    pub message: String,
+}
+
+// Warnings raised by an action that went ahead, kept for audit
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ComplianceRecord {
+    pub id: String,
+    pub shift_id: ShiftId,
+    pub worker_id: Option<UserId>,
+    pub jurisdiction: String,
+    pub action: String,
+    pub findings: Vec<ComplianceFinding>,
+    pub recorded_at: i64,
+}
+
+// Events
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum EventStatus {
+    Active,
+    Cancelled,
+}
+
+// One occasion run by a business, grouping shifts of different roles. Every
+// shift of the event falls within its window.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Event {
+    pub id: EventId,
+    pub business_id: UserId,
+    pub name: String,
+    pub description: Option<String>,
+    pub location: String,
+    pub coordinates: Option<GeoPoint>,
+    pub starts_at: i64, // ms since the epoch, UTC
+    pub ends_at: i64,
+    pub utc_offset_minutes: i16,
+    pub time_zone: Option<String>,
+    pub status: EventStatus,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RoleStaffing {
+    pub role: String,
+    pub shift_ids: Vec<ShiftId>,
+    pub headcount: u32,
+    pub filled: u32,
+    pub open_seats: u32, // unfilled seats on shifts still taking applications
+}
+
+// Staffing progress and cost of an event; cancelled and expired shifts are left out
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EventSummary {
+    pub event: Event,
+    pub roles: Vec<RoleStaffing>,
+    pub headcount: u32,
+    pub filled: u32,
+    pub estimated_cost: Vec<Money>, // scheduled hours x pay rate x headcount, one entry per currency
+    pub earned_cost: Vec<Money>,    // pay recorded in work history so far
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EventShiftError {
+    pub shift_id: ShiftId,
+    pub error: String,
+}
+
+// Outcome of a bulk operation: shifts that were changed and shifts that
+// could not be, with the reason
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EventBulkResult {
+    pub event: Event,
+    pub updated: Vec<ShiftId>,
+    pub skipped: Vec<EventShiftError>,
+}
+
+// Shift search; unset fields don't filter
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct ShiftSearchCriteria {
+    pub location: Option<String>,      // case-insensitive substring
+    pub min_pay: Option<Money>,        // hourly, only shifts paid in the same currency
+    pub starts_after: Option<i64>,     // ms since epoch, inclusive
+    pub starts_before: Option<i64>,
+    pub role: Option<String>,          // case-insensitive exact match
+    pub urgent_only: Option<bool>,
+    pub match_skills: Option<bool>,    // requirements overlap the caller's skills
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct NearbyShift {
+    pub shift: Shift,
+    pub distance_km: f64,
+}
+
+// Paging
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+    Date,      // when the work happens (shift start, completion)
+    PayRate,   // compared in minor units, regardless of currency
+    CreatedAt,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+// Every list endpoint takes one of these; unset fields fall back to the
+// endpoint's default order and DEFAULT_PAGE_SIZE.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct PageRequest {
+    pub cursor: Option<String>, // next_cursor of the previous page
+    pub limit: Option<u32>,
+    pub sort_by: Option<SortKey>,
+    pub direction: Option<SortDirection>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>, // None on the last page
+    pub total: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct NotificationPage {
+    pub items: Vec<Notification>,
+    pub next_cursor: Option<String>,
+    pub total: Option<u64>,
+    pub unread_count: u64,
+}
+
+// Notification Preferences
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum NotificationChannel {
+    InApp,
+    Outbound, // in-app plus delivery outside the canister
+}
+
+// Daily window in the user's local time, in minutes since midnight; the end
+// may be earlier than the start for windows that span midnight
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct QuietHours {
+    pub start_minute: u16,
+    pub end_minute: u16,
+    pub utc_offset_minutes: i16,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct NotificationPreferences {
+    pub muted_types: Vec<NotificationType>,
+    pub urgent_types: Vec<NotificationType>, // delivered even during quiet hours
+    pub quiet_hours: Option<QuietHours>,
+    pub channel: NotificationChannel,
+    pub updated_at: i64,
+    pub daily_digest: Option<bool>, // collapse ShiftPosted into one summary a day
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        NotificationPreferences {
+            muted_types: Vec::new(),
+            urgent_types: Vec::new(),
+            quiet_hours: None,
+            channel: NotificationChannel::InApp,
+            updated_at: 0,
+            daily_digest: None,
+        }
+    }
+}
+
+// A ShiftPosted event held back for the recipient's daily digest
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DigestEntry {
+    pub shift_id: Option<ShiftId>,
+    pub summary: String,
+    pub created_at: i64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct NotificationRetention {
+    pub retention_days: u32, // read notifications older than this are deleted
+    pub max_per_user: u32, // oldest notifications beyond this are deleted
+}
+
+// Outbound delivery job for a notification routed to the Outbound channel
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DeliveryJob {
+    pub notification_id: String,
+    pub user_id: UserId,
+    pub attempts: u32,
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+}
+
+// Shift Payment Escrow
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum EscrowStatus {
+    Funding,
+    Funded,
+    Settling,
+    Released,
+    Refunded,
+}
+
+// Funds pulled from the business into a per-seat subaccount when a worker is
+// approved, paid out to the worker on completion with any difference refunded
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Escrow {
+    pub shift_id: ShiftId,
+    pub business_id: UserId,
+    pub business_principal: Principal,
+    pub worker_id: UserId,
+    pub subaccount: Vec<u8>,
+    pub amount: u64,
+    pub status: EscrowStatus,
+    pub funding_block: Option<Nat>,
+    pub released_amount: u64,
+    pub release_block: Option<Nat>,
+    pub refunded_amount: u64,
+    pub refund_block: Option<Nat>,
+    pub created_at: i64,
+    pub settled_at: Option<i64>,
+    pub last_error: Option<String>,
+    // Outstanding early wage advances netted out of the worker's share, fixed
+    // at the first settlement attempt and paid back to the advance pool
+    pub advance_repayment: Option<u64>,
+    pub repayment_block: Option<Nat>,
+}
+
+// Early Wage Access
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AdvanceStatus {
+    Pending,
+    Paid,
+    Repaid,
+    Failed,
+}
+
+// Part of an advance (principal plus fee) drawn against one work history entry
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AdvanceAllocation {
+    pub work_history_id: WorkHistoryId,
+    pub amount_minor: u64,
+    pub repaid_at: Option<i64>,
+}
+
+// Payout from the canister's advance pool against completed, unpaid work.
+// The debt is amount + fee, recovered when the business pays for the work.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Advance {
+    pub id: AdvanceId,
+    pub worker_id: UserId,
+    pub amount: Money,
+    pub fee: Money,
+    pub allocations: Vec<AdvanceAllocation>,
+    pub status: AdvanceStatus,
+    pub payout_block: Option<Nat>,
+    pub requested_at: i64,
+    pub repaid_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AdvanceBalance {
+    pub currency: String,
+    pub unsettled_earnings: u64,
+    pub outstanding: u64,
+    pub available: u64,
+    pub cap_percent: u8,
+    pub fee_bps: u16,
+}
+
+// ICRC-1 / ICRC-2 ledger interface
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+// Canister Configuration
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct InitArgs {
+    pub ecdsa_key_name: Option<String>,
+    pub ledger_canister_id: Option<Principal>,
+    pub ledger_decimals: Option<u8>,
+    pub default_currency: Option<String>,
+    pub advance_cap_percent: Option<u8>,
+    pub advance_fee_bps: Option<u16>,
+    pub sweep_interval_secs: Option<u64>,
+    pub expiry_grace_minutes: Option<u32>,
+    pub checkout_grace_minutes: Option<u32>,
+    pub checkin_grace_minutes: Option<u32>,
+    pub min_shift_gap_minutes: Option<u32>,
+    pub delivery_relay_url: Option<String>,
+    pub delivery_hmac_secret: Option<String>,
+    pub delivery_interval_secs: Option<u64>,
+    pub delivery_max_attempts: Option<u32>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CanisterConfig {
+    pub ecdsa_key_name: String,
+    pub ecdsa_public_key: Option<Vec<u8>>,
+    pub ledger_canister_id: Option<Principal>,
+    pub ledger_decimals: Option<u8>,
+    pub default_currency: Option<String>,
+    pub advance_cap_percent: Option<u8>,
+    pub advance_fee_bps: Option<u16>,
+    pub sweep_interval_secs: Option<u64>,
+    pub expiry_grace_minutes: Option<u32>,
+    pub checkout_grace_minutes: Option<u32>,
+    pub checkin_grace_minutes: Option<u32>,
+    pub min_shift_gap_minutes: Option<u32>,
+    pub delivery_relay_url: Option<String>,
+    pub delivery_hmac_secret: Option<String>,
+    pub delivery_interval_secs: Option<u64>,
+    pub delivery_max_attempts: Option<u32>,
+    pub notification_retention_days: Option<u32>,
+    pub max_notifications_per_user: Option<u32>,
+}
+
+impl Default for CanisterConfig {
+    fn default() -> Self {
+        CanisterConfig {
+            ecdsa_key_name: DEFAULT_ECDSA_KEY_NAME.to_string(),
+            ecdsa_public_key: None,
+            ledger_canister_id: None,
+            ledger_decimals: None,
+            default_currency: None,
+            advance_cap_percent: None,
+            advance_fee_bps: None,
+            sweep_interval_secs: None,
+            expiry_grace_minutes: None,
+            checkout_grace_minutes: None,
+            checkin_grace_minutes: None,
+            min_shift_gap_minutes: None,
+            delivery_relay_url: None,
+            delivery_hmac_secret: None,
+            delivery_interval_secs: None,
+            delivery_max_attempts: None,
+            notification_retention_days: None,
+            max_notifications_per_user: None,
+        }
+    }
+}
+
+// Local replica / PocketIC test key; mainnet deployments pass "key_1" at install
+const DEFAULT_ECDSA_KEY_NAME: &str = "dfx_test_key";
+const DID_DERIVATION_PATH: &[u8] = b"flexwage_did";
+
+// W3C export vocabulary
+const DID_CONTEXT: &str = "https://www.w3.org/ns/did/v1";
+const SECP256K1_CONTEXT: &str = "https://w3id.org/security/suites/secp256k1-2019/v1";
+const VC_CONTEXT: &str = "https://www.w3.org/2018/credentials/v1";
+const FLEXWAGE_VOCAB: &str = "urn:flexwage:vocab#";
+const SIGNING_KEY_FRAGMENT: &str = "flexwage-signing-key";
+const MAX_NONCE_LENGTH: usize = 128;
+// Each presentation costs a threshold signature, so workers get a budget
+const MAX_PRESENTATIONS_PER_HOUR: usize = 10;
+const MAX_REVOCATION_REASON_LENGTH: usize = 256;
+const DEFAULT_LEDGER_DECIMALS: u8 = 8;
+const DEFAULT_CURRENCY: &str = "USD";
+// Share of unsettled earnings a worker can draw early, and the fee on each advance
+const DEFAULT_ADVANCE_CAP_PERCENT: u8 = 50;
+const DEFAULT_ADVANCE_FEE_BPS: u16 = 100;
+// Shift sweep: how often it runs, how long after the start an unfilled shift
+// stays listed, and how long after the end a missing check-out is tolerated
+// (and still paid)
+const DEFAULT_SWEEP_INTERVAL_SECS: u64 = 300;
+const DEFAULT_EXPIRY_GRACE_MINUTES: u32 = 0;
+const DEFAULT_CHECKOUT_GRACE_MINUTES: u32 = 30;
+// How early before the start workers may check in
+const DEFAULT_CHECKIN_GRACE_MINUTES: u32 = 30;
+const MAX_SHIFT_DURATION_MS: i64 = 24 * 3_600_000;
+const MAX_SHIFT_HEADCOUNT: u32 = 100;
+const MAX_UTC_OFFSET_MINUTES: i16 = 18 * 60;
+// Minimum travel/rest time between two shifts booked for the same worker
+const DEFAULT_MIN_SHIFT_GAP_MINUTES: u32 = 0;
+const DAY_MS: i64 = 24 * 3_600_000;
+const MAX_JURISDICTION_LENGTH: usize = 32;
+const MAX_EVENT_DURATION_MS: i64 = 14 * DAY_MS;
+const MAX_EVENT_NAME_LENGTH: usize = 120;
+// Outbound delivery: queue polling, retry limit and exponential backoff
+// between attempts, starting at the base and capped at the max
+const DEFAULT_DELIVERY_INTERVAL_SECS: u64 = 60;
+const DEFAULT_DELIVERY_MAX_ATTEMPTS: u32 = 5;
+const DELIVERY_BACKOFF_BASE_MS: i64 = 30_000;
+const DELIVERY_BACKOFF_MAX_MS: i64 = 3_600_000;
+const DELIVERY_BATCH_SIZE: usize = 10;
+const DELIVERY_MAX_RESPONSE_BYTES: u64 = 4096;
+// Unused cycles attached to an outcall are refunded
+const DELIVERY_CYCLES: u128 = 2_000_000_000;
+// Notification housekeeping: retention defaults, how often it runs, the most
+// deletions per run, and how long ShiftPosted events wait for a digest
+const DEFAULT_NOTIFICATION_RETENTION_DAYS: u32 = 30;
+const DEFAULT_MAX_NOTIFICATIONS_PER_USER: u32 = 500;
+const NOTIFICATION_MAINTENANCE_INTERVAL_SECS: u64 = 3600;
+const MAX_RETENTION_DELETES_PER_RUN: usize = 1000;
+const DIGEST_PERIOD_MS: i64 = 24 * 3_600_000;
+const MAX_DIGEST_LINES: usize = 5;
+// Stored notifications are bounded at 1024 bytes and digest entries at 512,
+// so their text is cut to these before it is stored
+const MAX_NOTIFICATION_TITLE_BYTES: usize = 120;
+const MAX_NOTIFICATION_MESSAGE_BYTES: usize = 480;
+const MAX_DIGEST_LINE_BYTES: usize = 80;
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+// Geospatial search: shifts are indexed by a 6-character geohash (cells of
+// roughly 1.2 x 0.6 km); a query reads at most MAX_GEO_CELLS cells
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+const GEOHASH_PRECISION: usize = 6;
+const MAX_GEO_CELLS: usize = 64;
+const MAX_SEARCH_RADIUS_KM: f64 = 100.0;
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+// Workers whose home area is this close to a new shift hear about it, up
+// to MAX_SHIFT_POSTED_NOTIFICATIONS of them, nearest first
+const SHIFT_POSTED_RADIUS_KM: f64 = 10.0;
+const MAX_SHIFT_POSTED_NOTIFICATIONS: usize = 200;
+
+// Record layouts from earlier canister versions: V1 stored pay as f32, V2
+// stored the schedule as date and time strings, V3 had a single assigned
+// worker with check-in state on the shift itself. Only decoded when reading
+// stable data written by an older version.
+#[derive(CandidType, Deserialize)]
+struct LegacyShiftV1 {
+    id: ShiftId,
+    business_id: UserId,
+    role: String,
+    date: String,
+    start_time: String,
+    end_time: String,
+    pay_rate: f32,
+    location: String,
+    description: Option<String>,
+    requirements: Vec<String>,
+    status: ShiftStatus,
+    assigned_worker: Option<UserId>,
+    applicants: Vec<UserId>,
+    is_urgent: bool,
+    created_at: i64,
+    updated_at: i64,
+    checked_in_at: Option<i64>,
+    checked_out_at: Option<i64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyShiftV2 {
+    id: ShiftId,
+    business_id: UserId,
+    role: String,
+    date: String,
+    start_time: String,
+    end_time: String,
+    pay_rate: Money,
+    location: String,
+    description: Option<String>,
+    requirements: Vec<String>,
+    status: ShiftStatus,
+    assigned_worker: Option<UserId>,
+    applicants: Vec<UserId>,
+    is_urgent: bool,
+    created_at: i64,
+    updated_at: i64,
+    checked_in_at: Option<i64>,
+    checked_out_at: Option<i64>,
+    checkout_overdue_at: Option<i64>,
+}
+
+impl From<LegacyShiftV1> for LegacyShiftV2 {
+    fn from(legacy: LegacyShiftV1) -> Self {
+        LegacyShiftV2 {
+            id: legacy.id,
+            business_id: legacy.business_id,
+            role: legacy.role,
+            date: legacy.date,
+            start_time: legacy.start_time,
+            end_time: legacy.end_time,
+            pay_rate: Money::from_major(legacy.pay_rate as f64, &default_currency()),
+            location: legacy.location,
+            description: legacy.description,
+            requirements: legacy.requirements,
+            status: legacy.status,
+            assigned_worker: legacy.assigned_worker,
+            applicants: legacy.applicants,
+            is_urgent: legacy.is_urgent,
+            created_at: legacy.created_at,
+            updated_at: legacy.updated_at,
+            checked_in_at: legacy.checked_in_at,
+            checked_out_at: legacy.checked_out_at,
+            checkout_overdue_at: None,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyShiftV3 {
+    id: ShiftId,
+    business_id: UserId,
+    role: String,
+    starts_at: i64,
+    ends_at: i64,
+    utc_offset_minutes: i16,
+    time_zone: Option<String>,
+    pay_rate: Money,
+    location: String,
+    coordinates: Option<GeoPoint>,
+    jurisdiction: Option<String>,
+    description: Option<String>,
+    requirements: Vec<String>,
+    status: ShiftStatus,
+    assigned_worker: Option<UserId>,
+    applicants: Vec<UserId>,
+    is_urgent: bool,
+    created_at: i64,
+    updated_at: i64,
+    checked_in_at: Option<i64>,
+    checked_out_at: Option<i64>,
+    checkout_overdue_at: Option<i64>,
+}
+
+impl From<LegacyShiftV2> for LegacyShiftV3 {
+    fn from(legacy: LegacyShiftV2) -> Self {
+        // The strings were always read as UTC; unparseable schedules collapse
+        // to the creation time so the record still loads
+        let (starts_at, ends_at) = parse_legacy_schedule(&legacy.date, &legacy.start_time, &legacy.end_time)
+            .unwrap_or_else(|error| {
+                ic_cdk::println!("Shift {} has an unreadable schedule: {}", legacy.id, error);
+                (legacy.created_at, legacy.created_at)
+            });
+        LegacyShiftV3 {
+            id: legacy.id,
+            business_id: legacy.business_id,
+            role: legacy.role,
+            starts_at,
+            ends_at,
+            utc_offset_minutes: 0,
+            time_zone: None,
+            pay_rate: legacy.pay_rate,
+            location: legacy.location,
+            coordinates: None,
+            jurisdiction: None,
+            description: legacy.description,
+            requirements: legacy.requirements,
+            status: legacy.status,
+            assigned_worker: legacy.assigned_worker,
+            applicants: legacy.applicants,
+            is_urgent: legacy.is_urgent,
+            created_at: legacy.created_at,
+            updated_at: legacy.updated_at,
+            checked_in_at: legacy.checked_in_at,
+            checked_out_at: legacy.checked_out_at,
+            checkout_overdue_at: legacy.checkout_overdue_at,
+        }
+    }
+}
+
+impl From<LegacyShiftV3> for Shift {
+    fn from(legacy: LegacyShiftV3) -> Self {
+        // The assigned worker becomes the only seat; the approval time was
+        // not recorded, so the last update stands in for it
+        let seats = legacy.assigned_worker
+            .map(|worker_id| ShiftSeat {
+                worker_id,
+                status: if legacy.checked_out_at.is_some() {
+                    SeatStatus::Completed
+                } else if legacy.checked_in_at.is_some() {
+                    SeatStatus::CheckedIn
+                } else {
+                    SeatStatus::Assigned
+                },
+                assigned_at: legacy.updated_at,
+                checked_in_at: legacy.checked_in_at,
+                checked_out_at: legacy.checked_out_at,
+                checkout_overdue_at: legacy.checkout_overdue_at,
+                work_history_id: None,
+            })
+            .into_iter()
+            .collect();
+        Shift {
+            id: legacy.id,
+            business_id: legacy.business_id,
+            event_id: None,
+            role: legacy.role,
+            starts_at: legacy.starts_at,
+            ends_at: legacy.ends_at,
+            utc_offset_minutes: legacy.utc_offset_minutes,
+            time_zone: legacy.time_zone,
+            pay_rate: legacy.pay_rate,
+            location: legacy.location,
+            coordinates: legacy.coordinates,
+            jurisdiction: legacy.jurisdiction,
+            description: legacy.description,
+            requirements: legacy.requirements,
+            status: legacy.status,
+            headcount: 1,
+            seats,
+            applicants: legacy.applicants,
+            is_urgent: legacy.is_urgent,
+            created_at: legacy.created_at,
+            updated_at: legacy.updated_at,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyWorkHistory {
+    id: WorkHistoryId,
+    worker_id: UserId,
+    business_id: UserId,
+    shift_id: ShiftId,
+    role: String,
+    date_worked: String,
+    hours_worked: f32,
+    pay_earned: f32,
+    business_name: String,
+    location: String,
+    completed_at: i64,
+    verification_hash: String,
+    confirmed_at: Option<i64>,
+    signature: Option<String>,
+    settled_at: Option<i64>,
+}
+
+impl From<LegacyWorkHistory> for WorkHistory {
+    fn from(legacy: LegacyWorkHistory) -> Self {
+        WorkHistory {
+            id: legacy.id,
+            worker_id: legacy.worker_id,
+            business_id: legacy.business_id,
+            shift_id: legacy.shift_id,
+            role: legacy.role,
+            date_worked: legacy.date_worked,
+            hours_worked: legacy.hours_worked,
+            minutes_worked: None,
+            pay_earned: Money::from_major(legacy.pay_earned as f64, &default_currency()),
+            business_name: legacy.business_name,
+            location: legacy.location,
+            completed_at: legacy.completed_at,
+            verification_hash: legacy.verification_hash,
+            confirmed_at: legacy.confirmed_at,
+            signature: legacy.signature,
+            settled_at: legacy.settled_at,
+        }
+    }
 }
 
 // Storable trait implementations for stable structures
@@ -187,7 +1199,7 @@ impl ic_stable_structures::Storable for UserProfile {
         is_fixed_size: false,
     };
     
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
@@ -201,7 +1213,7 @@ impl ic_stable_structures::Storable for WorkerProfile {
         is_fixed_size: false,
     };
     
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
@@ -215,7 +1227,7 @@ impl ic_stable_structures::Storable for BusinessProfile {
         is_fixed_size: false,
     };
     
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
@@ -223,42 +1235,51 @@ impl ic_stable_structures::Storable for BusinessProfile {
     }
 }
 
+// Unbounded since seats grow with the headcount
 impl ic_stable_structures::Storable for Shift {
-    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
-        max_size: 2048,
-        is_fixed_size: false,
-    };
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
     
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
-    }
+        candid::decode_one(&bytes)
+            .or_else(|_| candid::decode_one::<LegacyShiftV3>(&bytes).map(Shift::from))
+            .or_else(|_| {
+                candid::decode_one::<LegacyShiftV2>(&bytes).map(|legacy| Shift::from(LegacyShiftV3::from(legacy)))
+            })
+            .or_else(|_| {
+                candid::decode_one::<LegacyShiftV1>(&bytes)
+                    .map(|legacy| Shift::from(LegacyShiftV3::from(LegacyShiftV2::from(legacy))))
+            })
+            .unwrap()
+    }
 }
 
 impl ic_stable_structures::Storable for WorkHistory {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
-        max_size: 1024,
+        max_size: 2048,
         is_fixed_size: false,
     };
     
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        candid::decode_one(&bytes)
+            .or_else(|_| candid::decode_one::<LegacyWorkHistory>(&bytes).map(WorkHistory::from))
+            .unwrap()
     }
 }
 
 impl ic_stable_structures::Storable for Rating {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
-        max_size: 1024,
+        max_size: 2048,
         is_fixed_size: false,
     };
     
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(candid::encode_one(&self).unwrap())
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
     }
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
         candid::decode_one(&bytes).unwrap()
@@ -271,7 +1292,7 @@ impl ic_stable_structures::Storable for DIDDocument {
         is_fixed_size: false,
     };
     
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
@@ -285,7 +1306,7 @@ impl ic_stable_structures::Storable for ShiftApplication {
         is_fixed_size: false,
     };
     
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
@@ -299,7 +1320,146 @@ impl ic_stable_structures::Storable for Notification {
         is_fixed_size: false,
     };
     
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::Storable for NotificationPreferences {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 512,
+        is_fixed_size: false,
+    };
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::Storable for DeliveryJob {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 1024,
+        is_fixed_size: false,
+    };
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::Storable for DigestEntry {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 512,
+        is_fixed_size: false,
+    };
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::Storable for CanisterConfig {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::Storable for PresentationRecord {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 1024,
+        is_fixed_size: false,
+    };
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::Storable for CredentialStatus {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 1024,
+        is_fixed_size: false,
+    };
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::Storable for Escrow {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 1024,
+        is_fixed_size: false,
+    };
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::Storable for Advance {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::Storable for LaborRuleSet {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::Storable for Event {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl ic_stable_structures::Storable for ComplianceRecord {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
@@ -307,6 +1467,114 @@ impl ic_stable_structures::Storable for Notification {
     }
 }
 
+// Index maps are keyed by tuples of ids. ic-stable-structures only stores
+// tuples whose parts are all bounded, so IndexMap wraps each key and writes
+// the parts length-prefixed; the map orders entries by the decoded tuple.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct CompositeKey<K>(K);
+
+fn write_key_part<T: ic_stable_structures::Storable>(bytes: &mut Vec<u8>, part: &T) {
+    let part = part.to_bytes();
+    bytes.extend_from_slice(&(part.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&part);
+}
+
+fn read_key_part<T: ic_stable_structures::Storable>(bytes: &mut &[u8]) -> T {
+    let (len, rest) = bytes.split_at(4);
+    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+    let (part, rest) = rest.split_at(len);
+    *bytes = rest;
+    T::from_bytes(Cow::Borrowed(part))
+}
+
+impl<A, B> ic_stable_structures::Storable for CompositeKey<(A, B)>
+where
+    A: ic_stable_structures::Storable,
+    B: ic_stable_structures::Storable,
+{
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        let mut bytes = Vec::new();
+        write_key_part(&mut bytes, &self.0.0);
+        write_key_part(&mut bytes, &self.0.1);
+        Cow::Owned(bytes)
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let mut bytes = bytes.as_ref();
+        CompositeKey((read_key_part(&mut bytes), read_key_part(&mut bytes)))
+    }
+}
+
+impl<A, B, C> ic_stable_structures::Storable for CompositeKey<(A, B, C)>
+where
+    A: ic_stable_structures::Storable,
+    B: ic_stable_structures::Storable,
+    C: ic_stable_structures::Storable,
+{
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+    
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        let mut bytes = Vec::new();
+        write_key_part(&mut bytes, &self.0.0);
+        write_key_part(&mut bytes, &self.0.1);
+        write_key_part(&mut bytes, &self.0.2);
+        Cow::Owned(bytes)
+    }
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let mut bytes = bytes.as_ref();
+        CompositeKey((read_key_part(&mut bytes), read_key_part(&mut bytes), read_key_part(&mut bytes)))
+    }
+}
+
+// A StableBTreeMap over CompositeKey that takes and yields plain tuples
+struct IndexMap<K, V>(StableBTreeMap<CompositeKey<K>, V, Memory>)
+where
+    K: Ord + Clone,
+    CompositeKey<K>: ic_stable_structures::Storable,
+    V: ic_stable_structures::Storable;
+
+impl<K, V> IndexMap<K, V>
+where
+    K: Ord + Clone,
+    CompositeKey<K>: ic_stable_structures::Storable,
+    V: ic_stable_structures::Storable,
+{
+    fn init(memory: Memory) -> Self {
+        IndexMap(StableBTreeMap::init(memory))
+    }
+    
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.0.insert(CompositeKey(key), value)
+    }
+    
+    fn get(&self, key: &K) -> Option<V> {
+        self.0.get(&CompositeKey(key.clone()))
+    }
+    
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.0.remove(&CompositeKey(key.clone()))
+    }
+    
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    
+    fn clear_new(&mut self) {
+        self.0.clear_new()
+    }
+    
+    fn iter(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.0.iter().map(|(key, value)| (key.0, value))
+    }
+    
+    fn range(&self, bounds: impl std::ops::RangeBounds<K>) -> impl DoubleEndedIterator<Item = (K, V)> + '_ {
+        let wrap = |bound: std::ops::Bound<&K>| bound.map(|key| CompositeKey(key.clone()));
+        self.0.range((wrap(bounds.start_bound()), wrap(bounds.end_bound())))
+            .map(|(key, value)| (key.0, value))
+    }
+}
+
 // Memory Manager
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
@@ -325,12 +1593,39 @@ thread_local! {
         )
     );
     
+    // Reverse of PRINCIPAL_TO_USERID, for paying and notifying a user by id
+    static USERID_TO_PRINCIPAL: RefCell<StableBTreeMap<UserId, Principal, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+        )
+    );
+    
     static WORKER_PROFILES: RefCell<StableBTreeMap<UserId, WorkerProfile, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
         )
     );
     
+    // (lowercased location, worker id), for shift-posted alerts
+    static WORKERS_BY_LOCATION: RefCell<IndexMap<(String, UserId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+        )
+    );
+    
+    // (geohash of home area, worker id)
+    static WORKERS_BY_GEOHASH: RefCell<IndexMap<(String, UserId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30)))
+        )
+    );
+    
+    static NOTIFICATION_PREFERENCES: RefCell<StableBTreeMap<UserId, NotificationPreferences, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)))
+        )
+    );
+    
     static BUSINESS_PROFILES: RefCell<StableBTreeMap<UserId, BusinessProfile, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
@@ -361,6 +1656,13 @@ thread_local! {
         )
     );
     
+    // Revocation status of every issued credential, keyed by credential id
+    static CREDENTIAL_STATUS: RefCell<StableBTreeMap<String, CredentialStatus, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+        )
+    );
+    
     static APPLICATIONS: RefCell<StableBTreeMap<String, ShiftApplication, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))
@@ -372,642 +1674,6179 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))
         )
     );
+    
+    // Per-user inbox index, value is the read flag
+    static NOTIFICATION_INDEX: RefCell<IndexMap<NotificationKey, bool>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+        )
+    );
+    
+    // Pending outbound deliveries ordered by when they are due: (due at, notification id)
+    static DELIVERY_QUEUE: RefCell<IndexMap<(u64, String), DeliveryJob>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)))
+        )
+    );
+    
+    // Deliveries that ran out of attempts, keyed by notification id
+    static DEAD_LETTERS: RefCell<StableBTreeMap<String, DeliveryJob, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+        )
+    );
+    
+    // Events waiting for each user's daily digest: (user, created_at, id)
+    static DIGEST_QUEUE: RefCell<IndexMap<(UserId, u64, String), DigestEntry>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)))
+        )
+    );
+    
+    static CONFIG: RefCell<StableCell<CanisterConfig, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))),
+            CanisterConfig::default()
+        ).expect("Failed to initialize canister config")
+    );
+    
+    // Last SCHEMA_VERSION whose upgrade migrations ran; 0 on canisters
+    // installed before versioning
+    static STORED_SCHEMA_VERSION: RefCell<StableCell<u32, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))),
+            0
+        ).expect("Failed to initialize schema version")
+    );
+    
+    static PRESENTATIONS: RefCell<StableBTreeMap<String, PresentationRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+        )
+    );
+    
+    // (worker, created at, presentation id), for the per-worker rate limit
+    static PRESENTATIONS_BY_WORKER: RefCell<IndexMap<(UserId, u64, String), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+        )
+    );
+    
+    // One escrow per seat, keyed by escrow_key
+    static ESCROWS: RefCell<StableBTreeMap<String, Escrow, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))
+        )
+    );
+    
+    static ADVANCES: RefCell<StableBTreeMap<AdvanceId, Advance, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+        )
+    );
+    
+    // (worker, requested at, advance id), for capacity checks and history
+    static ADVANCES_BY_WORKER: RefCell<IndexMap<(UserId, u64, AdvanceId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+        )
+    );
+    
+    // Secondary indexes, maintained by save_shift / remove_shift,
+    // save_work_history and save_rating
+    static SHIFTS_BY_BUSINESS: RefCell<IndexMap<(UserId, ShiftId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25)))
+        )
+    );
+    
+    // (status code, starts_at, shift id), so each status lists soonest first
+    static SHIFTS_BY_STATUS: RefCell<IndexMap<(u8, u64, ShiftId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26)))
+        )
+    );
+    
+    static WORK_HISTORY_BY_WORKER: RefCell<IndexMap<(UserId, WorkHistoryId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27)))
+        )
+    );
+    
+    static RATINGS_BY_WORKER: RefCell<IndexMap<(UserId, RatingId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28)))
+        )
+    );
+    
+    // (geohash, shift id) for open shifts with coordinates
+    static SHIFTS_BY_GEOHASH: RefCell<IndexMap<(String, ShiftId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29)))
+        )
+    );
+    
+    // (seated worker, shift id), for building a worker's schedule
+    static SHIFTS_BY_WORKER: RefCell<IndexMap<(UserId, ShiftId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)))
+        )
+    );
+    
+    // (worker, start, shift id) for every unfinished seat on an active shift,
+    // so a worker's schedule reads in start order. Maintained by save_shift.
+    static SCHEDULE_BY_WORKER: RefCell<IndexMap<(UserId, u64, ShiftId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)))
+        )
+    );
+    
+    // Keyed by normalised jurisdiction code
+    static LABOR_RULES: RefCell<StableBTreeMap<String, LaborRuleSet, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)))
+        )
+    );
+    
+    // (shift id, record id), so a shift's audit trail reads as one range
+    static COMPLIANCE_RECORDS: RefCell<IndexMap<(ShiftId, String), ComplianceRecord>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34)))
+        )
+    );
+    
+    // (shift id, recorded at, record id), the order compliance records are paged in
+    static COMPLIANCE_RECORDS_BY_TIME: RefCell<IndexMap<(ShiftId, u64, String), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35)))
+        )
+    );
+    
+    static EVENTS: RefCell<StableBTreeMap<EventId, Event, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36)))
+        )
+    );
+    
+    // Maintained by save_event
+    static EVENTS_BY_BUSINESS: RefCell<IndexMap<(UserId, EventId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37)))
+        )
+    );
+    
+    // Event membership, (event, shift)
+    static SHIFTS_BY_EVENT: RefCell<IndexMap<(EventId, ShiftId), ()>> = RefCell::new(
+        IndexMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38)))
+        )
+    );
+    
+    // Disambiguates ids generated within the same message
+    static ID_COUNTER: Cell<u64> = const { Cell::new(0) };
+    
+    // Keeps timer-driven delivery runs from overlapping while outcalls are in flight
+    static DELIVERY_RUNNING: Cell<bool> = const { Cell::new(false) };
 }
 
 // Helper functions
+#[cfg(not(test))]
 fn get_current_time() -> i64 {
     api::time() as i64 / 1_000_000 // Convert to milliseconds
 }
 
-fn generate_id() -> String {
-    // Generate ID using timestamp and caller principal for uniqueness
-    format!("{}_{}", api::time(), api::caller().to_text().chars().take(8).collect::<String>())
+// Unit tests run outside a canister, where the system clock is unavailable
+#[cfg(test)]
+fn get_current_time() -> i64 {
+    tests::NOW.with(|now| now.get())
 }
 
-fn generate_verification_hash(data: &str) -> String {
-    // Simple hash generation - in production, use a proper cryptographic hash
-    format!("{:x}", data.len() as u64 * api::time())
+fn generate_id() -> String {
+    // Generate ID using timestamp, caller principal and a per-message counter for uniqueness
+    let counter = ID_COUNTER.with(|counter| {
+        let next = counter.get().wrapping_add(1);
+        counter.set(next);
+        next
+    });
+    format!("{}_{}_{}", api::time(), api::caller().to_text().chars().take(8).collect::<String>(), counter)
 }
 
-fn calculate_average_rating(ratings: &[Rating]) -> Option<f32> {
-    if ratings.is_empty() {
-        return None;
+fn push_notification(
+    user_id: &UserId,
+    notification_type: NotificationType,
+    title: &str,
+    message: String,
+    related_shift_id: Option<ShiftId>,
+) {
+    let preferences = notification_preferences(user_id);
+    if preferences.muted_types.contains(&notification_type) {
+        return;
     }
-    let sum: u32 = ratings.iter().map(|r| r.rating as u32).sum();
-    Some(sum as f32 / ratings.len() as f32)
-}
-
-// User Management Functions
-#[update]
-fn create_user_profile(mut profile: UserProfile) -> Result<UserProfile, String> {
-    let caller = api::caller();
     
-    // Check if user already exists
-    if USER_PROFILES.with(|profiles| profiles.borrow().contains_key(&caller)) {
-        return Err("User profile already exists".to_string());
+    if notification_type == NotificationType::ShiftPosted && preferences.daily_digest == Some(true) {
+        let now = get_current_time();
+        let summary = truncate_text(&message, MAX_DIGEST_LINE_BYTES);
+        DIGEST_QUEUE.with(|queue| {
+            queue.borrow_mut().insert(
+                (user_id.clone(), now.max(0) as u64, generate_id()),
+                DigestEntry { shift_id: related_shift_id, summary, created_at: now },
+            )
+        });
+        return;
     }
     
-    let user_id = generate_id();
-    profile.id = user_id.clone();
-    profile.owner_principal = caller;
-    profile.created_at = get_current_time();
-    profile.updated_at = get_current_time();
+    store_notification(user_id, &preferences, notification_type, title, message, related_shift_id);
+}
+
+// Creates the notification itself, routing it by the recipient's channel and quiet hours
+fn store_notification(
+    user_id: &UserId,
+    preferences: &NotificationPreferences,
+    notification_type: NotificationType,
+    title: &str,
+    message: String,
+    related_shift_id: Option<ShiftId>,
+) {
+    let now = get_current_time();
+    let deliver_after = match (&preferences.channel, &preferences.quiet_hours) {
+        (NotificationChannel::Outbound, Some(quiet_hours)) if !preferences.urgent_types.contains(&notification_type) => {
+            quiet_hours_end(quiet_hours, now)
+        }
+        _ => None,
+    };
     
-    USER_PROFILES.with(|profiles| {
-        profiles.borrow_mut().insert(caller, profile.clone())
-    });
+    let notification = Notification {
+        id: generate_id(),
+        user_id: user_id.clone(),
+        notification_type,
+        title: truncate_text(title, MAX_NOTIFICATION_TITLE_BYTES),
+        message: truncate_text(&message, MAX_NOTIFICATION_MESSAGE_BYTES),
+        related_shift_id,
+        is_read: false,
+        created_at: now,
+        channel: Some(preferences.channel.clone()),
+        deliver_after,
+        delivered_at: None,
+    };
     
-    PRINCIPAL_TO_USERID.with(|mapping| {
-        mapping.borrow_mut().insert(caller, user_id)
+    if preferences.channel == NotificationChannel::Outbound && read_config().delivery_relay_url.is_some() {
+        enqueue_delivery(DeliveryJob {
+            notification_id: notification.id.clone(),
+            user_id: user_id.clone(),
+            attempts: 0,
+            next_attempt_at: deliver_after.unwrap_or(now),
+            last_error: None,
+            created_at: now,
+        });
+    }
+    save_notification(notification);
+}
+
+// Cuts text to at most max_bytes of UTF-8, marking the cut with an ellipsis
+fn truncate_text(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let mut end = max_bytes.saturating_sub('…'.len_utf8());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
+
+fn notification_preferences(user_id: &UserId) -> NotificationPreferences {
+    NOTIFICATION_PREFERENCES.with(|preferences| preferences.borrow().get(user_id))
+        .unwrap_or_default()
+}
+
+fn dedup_notification_types(types: &mut Vec<NotificationType>) {
+    let mut seen = Vec::new();
+    types.retain(|notification_type| {
+        let first = !seen.contains(notification_type);
+        if first {
+            seen.push(notification_type.clone());
+        }
+        first
     });
+}
+
+// When `now` falls inside the quiet window, the instant the window ends
+fn quiet_hours_end(quiet_hours: &QuietHours, now: i64) -> Option<i64> {
+    const MINUTES_PER_DAY: i64 = 24 * 60;
+    let local_minute = (now / 60_000 + quiet_hours.utc_offset_minutes as i64).rem_euclid(MINUTES_PER_DAY);
+    let start = quiet_hours.start_minute as i64;
+    let end = quiet_hours.end_minute as i64;
     
-    Ok(profile)
+    let inside = if start <= end {
+        (start..end).contains(&local_minute)
+    } else {
+        local_minute >= start || local_minute < end
+    };
+    if !inside {
+        return None;
+    }
+    let minutes_left = (end - local_minute).rem_euclid(MINUTES_PER_DAY);
+    Some((now / 60_000 + minutes_left) * 60_000)
 }
 
-#[query]
-fn get_user_profile(principal: Principal) -> Result<UserProfile, String> {
-    USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&principal)
-            .ok_or_else(|| "User profile not found".to_string())
+fn notification_index_key(notification: &Notification) -> NotificationKey {
+    (notification.user_id.clone(), Reverse(notification.created_at.max(0) as u64), notification.id.clone())
+}
+
+// Writes a notification and keeps the inbox index in step
+fn save_notification(notification: Notification) {
+    NOTIFICATION_INDEX.with(|index| {
+        index.borrow_mut().insert(notification_index_key(&notification), notification.is_read)
+    });
+    NOTIFICATIONS.with(|notifications| {
+        notifications.borrow_mut().insert(notification.id.clone(), notification)
+    });
+}
+
+fn remove_notification(notification: &Notification) {
+    NOTIFICATION_INDEX.with(|index| index.borrow_mut().remove(&notification_index_key(notification)));
+    NOTIFICATIONS.with(|notifications| notifications.borrow_mut().remove(&notification.id));
+}
+
+// Notifications stored before the inbox index existed are indexed on upgrade
+fn index_existing_notifications() {
+    if NOTIFICATION_INDEX.with(|index| !index.borrow().is_empty()) {
+        return;
+    }
+    let notifications: Vec<Notification> = NOTIFICATIONS.with(|notifications| {
+        notifications.borrow().iter().map(|(_, notification)| notification).collect()
+    });
+    NOTIFICATION_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for notification in notifications {
+            index.insert(notification_index_key(&notification), notification.is_read);
+        }
+    });
+}
+
+fn unread_notification_count(user_id: &UserId) -> u64 {
+    let start = (user_id.clone(), Reverse(u64::MAX), String::new());
+    NOTIFICATION_INDEX.with(|index| {
+        index.borrow().range(start..)
+            .take_while(|((owner, _, _), _)| owner == user_id)
+            .filter(|(_, is_read)| !is_read)
+            .count() as u64
     })
 }
 
-#[update]
-fn update_user_profile(mut profile: UserProfile) -> Result<UserProfile, String> {
-    let caller = api::caller();
-    
-    // Verify caller owns this profile
-    if profile.owner_principal != caller {
-        return Err("Unauthorized: Cannot update another user's profile".to_string());
+// Loads a notification addressed to the caller
+fn get_caller_notification(notification_id: &str) -> Result<Notification, String> {
+    let user_profile = get_caller_profile()?;
+    let notification = NOTIFICATIONS.with(|notifications| notifications.borrow().get(&notification_id.to_string()))
+        .ok_or_else(|| "Notification not found".to_string())?;
+    if notification.user_id != user_profile.id {
+        return Err("Notification not found".to_string());
+    }
+    Ok(notification)
+}
+
+fn seat_of<'a>(shift: &'a Shift, worker_id: &UserId) -> Option<&'a ShiftSeat> {
+    shift.seats.iter().find(|seat| seat.worker_id == *worker_id)
+}
+
+fn seat_of_mut<'a>(shift: &'a mut Shift, worker_id: &UserId) -> Option<&'a mut ShiftSeat> {
+    shift.seats.iter_mut().find(|seat| seat.worker_id == *worker_id)
+}
+
+fn seated_workers(shift: &Shift) -> Vec<UserId> {
+    shift.seats.iter().map(|seat| seat.worker_id.clone()).collect()
+}
+
+fn is_fully_staffed(shift: &Shift) -> bool {
+    shift.seats.len() as u32 >= shift.headcount
+}
+
+// Not yet finished, cancelled or expired
+fn is_active_shift(shift: &Shift) -> bool {
+    matches!(shift.status, ShiftStatus::Open | ShiftStatus::Claimed | ShiftStatus::Approved | ShiftStatus::InProgress)
+}
+
+// Workers with a live stake in a shift: the seated workers plus applicants
+// whose application has not been rejected
+fn shift_workers(shift: &Shift) -> Vec<UserId> {
+    let mut workers: Vec<UserId> = seated_workers(shift);
+    for applicant in &shift.applicants {
+        let app_key = format!("{}_{}", shift.id, applicant);
+        let rejected = APPLICATIONS.with(|apps| apps.borrow().get(&app_key))
+            .is_none_or(|app| app.status == ApplicationStatus::Rejected);
+        if !rejected && !workers.contains(applicant) {
+            workers.push(applicant.clone());
+        }
+    }
+    workers
+}
+
+// Workers to alert about a new shift, read from the worker indexes rather
+// than by scanning every profile
+fn shift_posted_recipients(shift: &Shift) -> Vec<UserId> {
+    // Location text is spelled many ways; coordinates catch the rest
+    let mut nearby: Vec<(f64, UserId)> = Vec::new();
+    if let Some(point) = &shift.coordinates {
+        let cells = geohash_cells(point, SHIFT_POSTED_RADIUS_KM);
+        let candidates: Vec<UserId> = WORKERS_BY_GEOHASH.with(|index| {
+            let index = index.borrow();
+            cells.iter()
+                .flat_map(|cell| {
+                    index.range((cell.clone(), String::new())..)
+                        .take_while(|((hash, _), _)| hash.starts_with(cell.as_str()))
+                        .map(|((_, worker_id), _)| worker_id)
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        });
+        WORKER_PROFILES.with(|profiles| {
+            let profiles = profiles.borrow();
+            for worker_id in candidates {
+                let distance = profiles.get(&worker_id)
+                    .and_then(|profile| profile.home_area)
+                    .map(|home| distance_km(&home, point));
+                if let Some(distance) = distance.filter(|d| *d <= SHIFT_POSTED_RADIUS_KM) {
+                    nearby.push((distance, worker_id));
+                }
+            }
+        });
+        nearby.sort_by(|a, b| a.0.total_cmp(&b.0));
     }
     
-    profile.updated_at = get_current_time();
-    
-    USER_PROFILES.with(|profiles| {
-        profiles.borrow_mut().insert(caller, profile.clone())
+    let location = location_key(&shift.location);
+    let mut workers: Vec<UserId> = nearby.into_iter().map(|(_, worker_id)| worker_id).collect();
+    WORKERS_BY_LOCATION.with(|index| {
+        workers.extend(index.borrow().range((location.clone(), String::new())..)
+            .take_while(|((key, _), _)| *key == location)
+            .map(|((_, worker_id), _)| worker_id)
+            .take(MAX_SHIFT_POSTED_NOTIFICATIONS));
     });
-    
-    Ok(profile)
+    let mut seen = std::collections::BTreeSet::new();
+    workers.retain(|worker_id| seen.insert(worker_id.clone()));
+    workers.truncate(MAX_SHIFT_POSTED_NOTIFICATIONS);
+    workers
 }
 
-// Worker Profile Functions
-#[update]
-fn create_worker_profile(profile: WorkerProfile) -> Result<WorkerProfile, String> {
-    let caller = api::caller();
-    
-    // Verify user exists and is a worker
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
-    
-    if user_profile.user_type != UserType::Worker {
-        return Err("User is not registered as a worker".to_string());
+fn notify_shift_posted(shift: &Shift) {
+    for worker_id in shift_posted_recipients(shift) {
+        push_notification(
+            &worker_id,
+            NotificationType::ShiftPosted,
+            "New shift posted",
+            format!("{} on {} at {}", shift.role, shift_local_date(shift), shift.location),
+            Some(shift.id.clone()),
+        );
     }
-    
-    if profile.user_id != user_profile.id {
-        return Err("User ID mismatch".to_string());
+}
+
+fn notify_shift_cancelled(shift: &Shift, workers: &[UserId]) {
+    for worker_id in workers {
+        push_notification(
+            worker_id,
+            NotificationType::ShiftCancelled,
+            "Shift cancelled",
+            format!("The {} shift on {} has been cancelled", shift.role, shift_local_date(shift)),
+            Some(shift.id.clone()),
+        );
     }
-    
-    WORKER_PROFILES.with(|profiles| {
-        profiles.borrow_mut().insert(profile.user_id.clone(), profile.clone())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    to_hex(&sha256(data))
+}
+
+// Canonical serialization used for verification hashes: compact JSON with keys
+// sorted lexicographically, covering every field except the hash, the signature
+// and later status stamps (sign-off, settlement). Anyone holding an exported
+// record can rebuild it offline.
+fn canonical_work_history(entry: &WorkHistory) -> String {
+    let mut canonical = json!({
+        "business_id": entry.business_id,
+        "business_name": entry.business_name,
+        "completed_at": entry.completed_at,
+        "date_worked": entry.date_worked,
+        "id": entry.id,
+        "location": entry.location,
+        "pay_earned": {
+            "amount_minor": entry.pay_earned.amount_minor,
+            "currency": entry.pay_earned.currency,
+        },
+        "role": entry.role,
+        "shift_id": entry.shift_id,
+        "worker_id": entry.worker_id,
     });
-    
-    // Initialize empty DID document
-    let did_doc = DIDDocument {
-        worker_id: profile.user_id.clone(),
-        work_history: Vec::new(),
-        ratings: Vec::new(),
-        total_shifts: 0,
-        average_rating: None,
-        skills_verified: profile.skills.clone(),
-        created_at: get_current_time(),
-        updated_at: get_current_time(),
-        signature: generate_verification_hash(&format!("did_{}", profile.user_id)),
+    // Floats print differently across JSON libraries, so entries hash whole
+    // minutes; older entries keep the hours their signatures were made over
+    match entry.minutes_worked {
+        Some(minutes) => canonical["minutes_worked"] = json!(minutes),
+        None => canonical["hours_worked"] = json!(entry.hours_worked),
+    }
+    canonical.to_string()
+}
+
+fn canonical_rating(rating: &Rating) -> String {
+    json!({
+        "business_id": rating.business_id,
+        "business_name": rating.business_name,
+        "comment": rating.comment,
+        "created_at": rating.created_at,
+        "date_worked": rating.date_worked,
+        "id": rating.id,
+        "rating": rating.rating,
+        "role": rating.role,
+        "shift_id": rating.shift_id,
+        "worker_id": rating.worker_id,
+    })
+    .to_string()
+}
+
+fn work_history_hash(entry: &WorkHistory) -> String {
+    sha256_hex(canonical_work_history(entry).as_bytes())
+}
+
+fn rating_hash(rating: &Rating) -> String {
+    sha256_hex(canonical_rating(rating).as_bytes())
+}
+
+// Canonical DID form, excluding the signature. Work history and rating ids are
+// covered here; the records themselves carry their own signatures. The
+// average rating is signed in hundredths, since float formatting varies
+// between JSON libraries.
+fn canonical_did_document(doc: &DIDDocument) -> String {
+    json!({
+        "average_rating_hundredths": doc.average_rating.map(rating_hundredths),
+        "created_at": doc.created_at,
+        "ratings": doc.ratings,
+        "skills_verified": doc.skills_verified,
+        "total_shifts": doc.total_shifts,
+        "updated_at": doc.updated_at,
+        "work_history": doc.work_history,
+        "worker_id": doc.worker_id,
+    })
+    .to_string()
+}
+
+fn format_rfc3339(timestamp_ms: i64) -> String {
+    OffsetDateTime::from_unix_timestamp_nanos(timestamp_ms as i128 * 1_000_000)
+        .ok()
+        .and_then(|datetime| datetime.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
+
+fn principal_did(principal: &Principal) -> String {
+    format!("did:icp:{}", principal.to_text())
+}
+
+fn find_user_principal(user_id: &UserId) -> Option<Principal> {
+    USERID_TO_PRINCIPAL.with(|mapping| mapping.borrow().get(user_id))
+}
+
+fn work_history_credential_id(id: &str) -> String {
+    format!("urn:flexwage:work-history:{}", id)
+}
+
+fn rating_credential_id(id: &str) -> String {
+    format!("urn:flexwage:rating:{}", id)
+}
+
+// Assigns the next status list index to a newly issued credential
+fn register_credential(kind: CredentialKind, record_id: &str, worker_id: &UserId, issuer_business_id: &UserId) {
+    let credential_id = match kind {
+        CredentialKind::WorkHistory => work_history_credential_id(record_id),
+        CredentialKind::Rating => rating_credential_id(record_id),
     };
     
-    DID_DOCUMENTS.with(|docs| {
-        docs.borrow_mut().insert(profile.user_id.clone(), did_doc)
+    CREDENTIAL_STATUS.with(|statuses| {
+        let mut statuses = statuses.borrow_mut();
+        if statuses.contains_key(&credential_id) {
+            return;
+        }
+        let status = CredentialStatus {
+            credential_id: credential_id.clone(),
+            kind,
+            record_id: record_id.to_string(),
+            worker_id: worker_id.clone(),
+            issuer_business_id: issuer_business_id.clone(),
+            status_index: statuses.len(),
+            revocation: None,
+        };
+        statuses.insert(credential_id, status);
     });
+}
+
+// Records issued before the registry existed get their indexes on upgrade
+fn register_existing_credentials() {
+    let history: Vec<WorkHistory> = WORK_HISTORY.with(|history| {
+        history.borrow().iter().map(|(_, entry)| entry).collect()
+    });
+    for entry in history {
+        register_credential(CredentialKind::WorkHistory, &entry.id, &entry.worker_id, &entry.business_id);
+    }
     
-    Ok(profile)
+    let ratings: Vec<Rating> = RATINGS.with(|ratings| {
+        ratings.borrow().iter().map(|(_, rating)| rating).collect()
+    });
+    for rating in ratings {
+        register_credential(CredentialKind::Rating, &rating.id, &rating.worker_id, &rating.business_id);
+    }
 }
 
-#[query]
-fn get_worker_profile(user_id: UserId) -> Result<WorkerProfile, String> {
-    WORKER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&user_id)
-            .ok_or_else(|| "Worker profile not found".to_string())
+fn is_revoked(credential_id: &str) -> bool {
+    CREDENTIAL_STATUS.with(|statuses| {
+        statuses.borrow().get(&credential_id.to_string())
+            .is_some_and(|status| status.revocation.is_some())
     })
 }
 
-#[update]
-fn update_worker_profile(profile: WorkerProfile) -> Result<WorkerProfile, String> {
-    let caller = api::caller();
+fn credential_status_entry(credential_id: &str) -> serde_json::Value {
+    CREDENTIAL_STATUS.with(|statuses| statuses.borrow().get(&credential_id.to_string()))
+        .map(|status| json!({
+            "id": format!("{}#{}", status_list_id(), status.status_index),
+            "type": "BitstringStatusListEntry",
+            "statusPurpose": "revocation",
+            "statusListIndex": status.status_index.to_string(),
+            "statusListCredential": status_list_id(),
+        }))
+        .unwrap_or_default()
+}
+
+fn status_list_id() -> String {
+    format!("urn:flexwage:status-list:{}", api::id().to_text())
+}
+
+// Recomputes the worker's aggregates from their non-revoked credentials
+fn refresh_worker_aggregates(worker_id: &UserId) {
+    let completed_shifts = worker_history(worker_id).iter()
+        .filter(|entry| !is_revoked(&work_history_credential_id(&entry.id)))
+        .count() as u64;
     
-    // Verify caller owns this profile
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
+    let active_ratings: Vec<Rating> = worker_ratings(worker_id).into_iter()
+        .filter(|rating| !is_revoked(&rating_credential_id(&rating.id)))
+        .collect();
+    let average_rating = calculate_average_rating(&active_ratings);
     
-    if profile.user_id != user_profile.id {
-        return Err("Unauthorized: Cannot update another user's profile".to_string());
-    }
+    DID_DOCUMENTS.with(|docs| {
+        let existing = docs.borrow().get(worker_id);
+        if let Some(mut did_doc) = existing {
+            did_doc.total_shifts = completed_shifts;
+            did_doc.average_rating = average_rating;
+            did_doc.updated_at = get_current_time();
+            did_doc.signature = String::new();
+            docs.borrow_mut().insert(worker_id.clone(), did_doc);
+        }
+    });
     
     WORKER_PROFILES.with(|profiles| {
-        profiles.borrow_mut().insert(profile.user_id.clone(), profile.clone())
+        let existing = profiles.borrow().get(worker_id);
+        if let Some(mut profile) = existing {
+            profile.total_shifts_completed = completed_shifts;
+            profile.average_rating = average_rating;
+            profiles.borrow_mut().insert(worker_id.clone(), profile);
+        }
     });
-    
-    Ok(profile)
 }
 
-// Business Profile Functions
-#[update]
-fn create_business_profile(profile: BusinessProfile) -> Result<BusinessProfile, String> {
-    let caller = api::caller();
-    
-    // Verify user exists and is a business
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
-    
-    if user_profile.user_type != UserType::Business {
-        return Err("User is not registered as a business".to_string());
-    }
-    
-    if profile.user_id != user_profile.id {
-        return Err("User ID mismatch".to_string());
+fn signing_key_reference() -> String {
+    format!("{}#{}", principal_did(&api::id()), SIGNING_KEY_FRAGMENT)
+}
+
+// Shared JSON-LD proof block for anything signed with the canister key
+fn signature_proof(signature: &str, digest: &str, created_at: i64) -> serde_json::Value {
+    json!({
+        "type": "EcdsaSecp256k1Signature2019",
+        "created": format_rfc3339(created_at),
+        "verificationMethod": signing_key_reference(),
+        "proofPurpose": "assertionMethod",
+        "canonicalization": "flexwage-sorted-json",
+        "digestAlgorithm": "sha-256",
+        "digest": digest,
+        "proofValue": signature,
+    })
+}
+
+fn work_history_credential(entry: &WorkHistory, subject_did: &str) -> serde_json::Value {
+    let mut credential = json!({
+        "@context": [VC_CONTEXT, { "@vocab": FLEXWAGE_VOCAB }],
+        "id": work_history_credential_id(&entry.id),
+        "type": ["VerifiableCredential", "WorkHistoryCredential"],
+        "issuer": principal_did(&api::id()),
+        "issuanceDate": format_rfc3339(entry.completed_at),
+        "credentialSubject": {
+            "id": subject_did,
+            "workHistory": serde_json::from_str::<serde_json::Value>(&canonical_work_history(entry))
+                .unwrap_or_default(),
+        },
+        "credentialStatus": credential_status_entry(&work_history_credential_id(&entry.id)),
+    });
+    if let Some(signature) = &entry.signature {
+        credential["proof"] = signature_proof(signature, &entry.verification_hash, entry.completed_at);
     }
-    
-    BUSINESS_PROFILES.with(|profiles| {
-        profiles.borrow_mut().insert(profile.user_id.clone(), profile.clone())
+    credential
+}
+
+fn rating_credential(rating: &Rating, subject_did: &str) -> serde_json::Value {
+    let mut credential = json!({
+        "@context": [VC_CONTEXT, { "@vocab": FLEXWAGE_VOCAB }],
+        "id": rating_credential_id(&rating.id),
+        "type": ["VerifiableCredential", "RatingCredential"],
+        "issuer": principal_did(&api::id()),
+        "issuanceDate": format_rfc3339(rating.created_at),
+        "credentialSubject": {
+            "id": subject_did,
+            "rating": serde_json::from_str::<serde_json::Value>(&canonical_rating(rating))
+                .unwrap_or_default(),
+        },
+        "credentialStatus": credential_status_entry(&rating_credential_id(&rating.id)),
     });
-    
-    Ok(profile)
+    if let Some(signature) = &rating.signature {
+        credential["proof"] = signature_proof(signature, &rating.verification_hash, rating.created_at);
+    }
+    credential
 }
 
-#[query]
-fn get_business_profile(user_id: UserId) -> Result<BusinessProfile, String> {
-    BUSINESS_PROFILES.with(|profiles| {
-        profiles.borrow().get(&user_id)
-            .ok_or_else(|| "Business profile not found".to_string())
-    })
+// Digest covered by a presentation proof: the canonical JSON of the
+// presentation with its proof removed.
+fn presentation_digest(presentation: &serde_json::Value) -> [u8; 32] {
+    let mut unsigned = presentation.clone();
+    if let Some(fields) = unsigned.as_object_mut() {
+        fields.remove("proof");
+    }
+    sha256(unsigned.to_string().as_bytes())
 }
 
-#[update]
-fn update_business_profile(profile: BusinessProfile) -> Result<BusinessProfile, String> {
-    let caller = api::caller();
+fn aggregate_claim(worker_id: &UserId, criteria: &AggregateCriteria) -> Result<serde_json::Value, String> {
+    let matching: Vec<WorkHistory> = worker_history(worker_id).into_iter()
+        .filter(|entry| !is_revoked(&work_history_credential_id(&entry.id)))
+        .filter(|entry| criteria.role.as_ref().is_none_or(|role| entry.role.eq_ignore_ascii_case(role)))
+        .filter(|entry| criteria.completed_after.is_none_or(|after| entry.completed_at >= after))
+        .filter(|entry| criteria.completed_before.is_none_or(|before| entry.completed_at < before))
+        .filter(|entry| criteria.business_type.as_ref().is_none_or(|business_type| {
+            BUSINESS_PROFILES.with(|profiles| {
+                profiles.borrow().get(&entry.business_id)
+                    .is_some_and(|profile| profile.business_type.eq_ignore_ascii_case(business_type))
+            })
+        }))
+        .collect();
     
-    // Verify caller owns this profile
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
+    let shift_ids: Vec<&ShiftId> = matching.iter().map(|entry| &entry.shift_id).collect();
+    let ratings: Vec<Rating> = worker_ratings(worker_id).into_iter()
+        .filter(|rating| shift_ids.contains(&&rating.shift_id))
+        .filter(|rating| !is_revoked(&rating_credential_id(&rating.id)))
+        .collect();
+    let average_rating = calculate_average_rating(&ratings);
     
-    if profile.user_id != user_profile.id {
-        return Err("Unauthorized: Cannot update another user's profile".to_string());
+    if let Some(minimum) = criteria.min_average_rating {
+        if average_rating.is_none_or(|average| average < minimum) {
+            return Err("Aggregate claim not satisfied: average rating below requested minimum".to_string());
+        }
     }
     
-    BUSINESS_PROFILES.with(|profiles| {
-        profiles.borrow_mut().insert(profile.user_id.clone(), profile.clone())
-    });
-    
-    Ok(profile)
+    Ok(json!({
+        "criteria": {
+            "businessType": criteria.business_type,
+            "role": criteria.role,
+            "completedAfter": criteria.completed_after.map(format_rfc3339),
+            "completedBefore": criteria.completed_before.map(format_rfc3339),
+            "minAverageRating": criteria.min_average_rating,
+        },
+        "shiftCount": matching.len(),
+        "totalMinutes": matching.iter().map(WorkHistory::worked_minutes).sum::<u64>(),
+        "ratingCount": ratings.len(),
+        "averageRating": average_rating,
+    }))
 }
 
-// Shift Management Functions
-#[update]
-fn create_shift(mut shift: Shift) -> Result<Shift, String> {
-    let caller = api::caller();
+// Reads the pre-typed "YYYY-MM-DD" date and "HH:MM" times as UTC instants in
+// ms; shifts ending before they start run past midnight
+fn parse_legacy_schedule(date: &str, start_time: &str, end_time: &str) -> Result<(i64, i64), String> {
+    let date_format = time::format_description::parse("[year]-[month]-[day]")
+        .map_err(|_| "Invalid date format description".to_string())?;
+    let time_format = time::format_description::parse("[hour]:[minute]")
+        .map_err(|_| "Invalid time format description".to_string())?;
+    let date = time::Date::parse(date, &date_format)
+        .map_err(|_| format!("Invalid shift date: {}", date))?;
+    let start = time::Time::parse(start_time, &time_format)
+        .map_err(|_| format!("Invalid shift start time: {}", start_time))?;
+    let end = time::Time::parse(end_time, &time_format)
+        .map_err(|_| format!("Invalid shift end time: {}", end_time))?;
     
-    // Verify caller is a business
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
-    
-    if user_profile.user_type != UserType::Business {
-        return Err("Only businesses can create shifts".to_string());
+    let mut minutes = (end - start).whole_minutes();
+    if minutes <= 0 {
+        minutes += 24 * 60;
     }
-    
-    if shift.business_id != user_profile.id {
-        return Err("Business ID mismatch".to_string());
+    let starts_at = date.with_time(start).assume_utc().unix_timestamp() * 1000;
+    Ok((starts_at, starts_at + minutes * 60_000))
+}
+
+fn is_valid_utc_offset(offset_minutes: i16) -> bool {
+    (-MAX_UTC_OFFSET_MINUTES..=MAX_UTC_OFFSET_MINUTES).contains(&offset_minutes)
+}
+
+// Offsets are checked when written, but records decoded from older layouts
+// were not, so every reader clamps them into range
+fn clamp_utc_offset(offset_minutes: i16) -> i16 {
+    offset_minutes.clamp(-MAX_UTC_OFFSET_MINUTES, MAX_UTC_OFFSET_MINUTES)
+}
+
+fn utc_offset(offset_minutes: i16) -> time::UtcOffset {
+    time::UtcOffset::from_whole_seconds(clamp_utc_offset(offset_minutes) as i32 * 60).unwrap_or(time::UtcOffset::UTC)
+}
+
+// Calendar date of the shift start at the shift's location, "YYYY-MM-DD".
+// Runs from the shift sweep, so it must not trap on any stored shift.
+fn shift_local_date(shift: &Shift) -> String {
+    OffsetDateTime::from_unix_timestamp_nanos(shift.starts_at as i128 * 1_000_000)
+        .map(|instant| {
+            let date = instant.to_offset(utc_offset(shift.utc_offset_minutes)).date();
+            format!("{:04}-{:02}-{:02}", date.year(), date.month() as u8, date.day())
+        })
+        .unwrap_or_default()
+}
+
+fn is_valid_time_zone(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.split('/').all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+        })
+}
+
+fn validate_shift_schedule(shift: &Shift) -> Result<(), String> {
+    if shift.ends_at <= shift.starts_at {
+        return Err("Shift must end after it starts".to_string());
     }
-    
-    let shift_id = generate_id();
-    shift.id = shift_id.clone();
-    shift.created_at = get_current_time();
-    shift.updated_at = get_current_time();
-    shift.applicants = Vec::new();
-    
-    SHIFTS.with(|shifts| {
-        shifts.borrow_mut().insert(shift_id, shift.clone())
-    });
-    
-    Ok(shift)
+    if shift.ends_at - shift.starts_at > MAX_SHIFT_DURATION_MS {
+        return Err("Shifts cannot be longer than 24 hours".to_string());
+    }
+    if !is_valid_utc_offset(shift.utc_offset_minutes) {
+        return Err("UTC offset must be within +/-18 hours".to_string());
+    }
+    if shift.time_zone.as_deref().is_some_and(|name| !is_valid_time_zone(name)) {
+        return Err("Time zone must be an IANA name such as Europe/Berlin".to_string());
+    }
+    Ok(())
 }
 
-#[query]
-fn get_shift(shift_id: ShiftId) -> Result<Shift, String> {
-    SHIFTS.with(|shifts| {
-        shifts.borrow().get(&shift_id)
-            .ok_or_else(|| "Shift not found".to_string())
+fn validate_headcount(headcount: u32) -> Result<(), String> {
+    if headcount == 0 || headcount > MAX_SHIFT_HEADCOUNT {
+        return Err(format!("Headcount must be between 1 and {}", MAX_SHIFT_HEADCOUNT));
+    }
+    Ok(())
+}
+
+fn default_currency() -> String {
+    read_config().default_currency.unwrap_or_else(|| DEFAULT_CURRENCY.to_string())
+}
+
+// Converts minor currency units into ledger base units, e.g. cents into e8s
+fn to_ledger_units(amount: &Money) -> u64 {
+    let decimals = read_config().ledger_decimals.unwrap_or(DEFAULT_LEDGER_DECIMALS) as u32;
+    let exponent = currency_exponent(&amount.currency);
+    let units = if decimals >= exponent {
+        amount.amount_minor as u128 * 10u128.pow(decimals - exponent)
+    } else {
+        amount.amount_minor as u128 / 10u128.pow(exponent - decimals)
+    };
+    units.min(u64::MAX as u128) as u64
+}
+
+// Inverse of to_ledger_units, rounding down to whole minor units
+fn from_ledger_units(units: u64, currency: &str) -> u64 {
+    let decimals = read_config().ledger_decimals.unwrap_or(DEFAULT_LEDGER_DECIMALS) as u32;
+    let exponent = currency_exponent(currency);
+    let minor = if decimals >= exponent {
+        units as u128 / 10u128.pow(decimals - exponent)
+    } else {
+        units as u128 * 10u128.pow(exponent - decimals)
+    };
+    minor.min(u64::MAX as u128) as u64
+}
+
+fn escrow_key(shift_id: &ShiftId, worker_id: &UserId) -> String {
+    format!("{}_{}", shift_id, worker_id)
+}
+
+fn escrow_subaccount(escrow_key: &str) -> Vec<u8> {
+    sha256(format!("escrow:{}", escrow_key).as_bytes()).to_vec()
+}
+
+fn shift_escrows(shift_id: &ShiftId) -> Vec<Escrow> {
+    let prefix = format!("{}_", shift_id);
+    ESCROWS.with(|escrows| {
+        escrows.borrow().range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, escrow)| escrow)
+            .collect()
     })
 }
 
-#[update]
-fn update_shift(shift_id: ShiftId, mut shift: Shift) -> Result<Shift, String> {
-    let caller = api::caller();
+fn nat_to_u64(value: &Nat) -> u64 {
+    u64::try_from(value.0.clone()).unwrap_or(u64::MAX)
+}
+
+async fn ledger_fee(ledger: Principal) -> Result<u64, String> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|(code, msg)| format!("Failed to fetch ledger fee: {:?} {}", code, msg))?;
+    Ok(nat_to_u64(&fee))
+}
+
+async fn ledger_transfer(ledger: Principal, from_subaccount: Option<Vec<u8>>, to: Account, amount: u64) -> Result<Nat, String> {
+    let args = TransferArg {
+        from_subaccount,
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let (result,): (Result<Nat, TransferError>,) = ic_cdk::call(ledger, "icrc1_transfer", (args,))
+        .await
+        .map_err(|(code, msg)| format!("Ledger transfer failed: {:?} {}", code, msg))?;
+    result.map_err(|error| format!("Ledger transfer rejected: {:?}", error))
+}
+
+async fn ledger_transfer_from(ledger: Principal, from: Account, to: Account, amount: u64) -> Result<Nat, String> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from,
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let (result,): (Result<Nat, TransferFromError>,) = ic_cdk::call(ledger, "icrc2_transfer_from", (args,))
+        .await
+        .map_err(|(code, msg)| format!("Ledger transfer_from failed: {:?} {}", code, msg))?;
+    result.map_err(|error| format!("Ledger transfer_from rejected: {:?}", error))
+}
+
+fn save_escrow(escrow: &Escrow) {
+    ESCROWS.with(|escrows| {
+        escrows.borrow_mut().insert(escrow_key(&escrow.shift_id, &escrow.worker_id), escrow.clone())
+    });
+}
+
+// Pays out a seat's funded escrow once the seat is finished: a completed seat
+// pays the worker what the work history says they earned and refunds the
+// rest; a released seat, a no-show or a cancelled shift refunds everything.
+// Each outgoing transfer carries the ledger fee. Partial progress is recorded
+// so retries never pay twice.
+async fn settle_escrow(shift_id: &ShiftId, worker_id: &UserId) -> Result<Option<Escrow>, String> {
+    let Some(mut escrow) = ESCROWS.with(|escrows| escrows.borrow().get(&escrow_key(shift_id, worker_id))) else {
+        return Ok(None);
+    };
+    if escrow.status != EscrowStatus::Funded {
+        return Ok(Some(escrow));
+    }
+    let ledger = read_config().ledger_canister_id
+        .ok_or_else(|| "Ledger canister is not configured".to_string())?;
     
-    // Get existing shift
-    let existing_shift = SHIFTS.with(|shifts| {
-        shifts.borrow().get(&shift_id)
-            .ok_or_else(|| "Shift not found".to_string())
-    })?;
+    let shift = SHIFTS.with(|shifts| shifts.borrow().get(shift_id))
+        .ok_or_else(|| "Shift not found".to_string())?;
     
-    // Verify caller owns this shift
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
+    let earned_entry = if seat_earned_pay(&shift, worker_id)? {
+        worker_history(worker_id).into_iter().find(|entry| entry.shift_id == *shift_id)
+    } else {
+        None
+    };
+    let worker_share = earned_entry.as_ref()
+        .map_or(0, |entry| to_ledger_units(&entry.pay_earned).min(escrow.amount));
     
-    if existing_shift.business_id != user_profile.id {
-        return Err("Unauthorized: Cannot update another business's shift".to_string());
+    // Fix the advance repayment on the first attempt; once set, no further
+    // advances can be drawn against this entry (see advance_capacity)
+    if escrow.advance_repayment.is_none() {
+        let repayment = match &earned_entry {
+            Some(entry) => to_ledger_units(&outstanding_advance_debt(entry)?),
+            None => 0,
+        };
+        escrow.advance_repayment = Some(repayment.min(worker_share));
     }
+    let owed = escrow.advance_repayment.unwrap_or(0);
     
-    shift.id = shift_id.clone();
-    shift.updated_at = get_current_time();
+    // Guard against concurrent settlement while we await the ledger
+    escrow.status = EscrowStatus::Settling;
+    save_escrow(&escrow);
     
-    SHIFTS.with(|shifts| {
-        shifts.borrow_mut().insert(shift_id, shift.clone())
-    });
+    let mut repayment = 0;
+    let result = async {
+        let fee = ledger_fee(ledger).await?;
+        let payout;
+        // A repayment already transferred on an earlier attempt stands
+        (payout, repayment) = if escrow.repayment_block.is_some() {
+            (worker_share - owed, owed)
+        } else {
+            split_worker_share(worker_share, owed, fee)
+        };
+        
+        if escrow.repayment_block.is_none() && repayment > 0 {
+            let to = Account { owner: api::id(), subaccount: None };
+            let block = ledger_transfer(ledger, Some(escrow.subaccount.clone()), to, repayment - fee).await?;
+            escrow.repayment_block = Some(block);
+        }
+        
+        if escrow.release_block.is_none() && payout > fee {
+            let worker_principal = find_user_principal(&escrow.worker_id)
+                .ok_or_else(|| "Worker principal not found".to_string())?;
+            let to = Account { owner: worker_principal, subaccount: None };
+            let block = ledger_transfer(ledger, Some(escrow.subaccount.clone()), to, payout - fee).await?;
+            escrow.release_block = Some(block);
+            escrow.released_amount = payout;
+        }
+        
+        let remainder = escrow.amount - escrow.released_amount - repayment;
+        if escrow.refund_block.is_none() && remainder > fee {
+            let to = Account { owner: escrow.business_principal, subaccount: None };
+            let block = ledger_transfer(ledger, Some(escrow.subaccount.clone()), to, remainder - fee).await?;
+            escrow.refund_block = Some(block);
+            escrow.refunded_amount = remainder;
+        }
+        Ok::<(), String>(())
+    }
+    .await;
     
-    Ok(shift)
+    match result {
+        Ok(()) => {
+            let now = get_current_time();
+            escrow.status = if escrow.released_amount > 0 || escrow.repayment_block.is_some() { EscrowStatus::Released } else { EscrowStatus::Refunded };
+            escrow.advance_repayment = Some(repayment);
+            escrow.settled_at = Some(now);
+            escrow.last_error = None;
+            save_escrow(&escrow);
+            
+            if let Some(mut entry) = earned_entry {
+                if repayment > 0 {
+                    mark_advances_repaid(&entry, now);
+                }
+                entry.settled_at = Some(now);
+                save_work_history(&entry);
+            }
+            
+            if escrow.released_amount > 0 {
+                push_notification(
+                    &escrow.worker_id,
+                    NotificationType::PaymentProcessed,
+                    "Payment released",
+                    format!("Your pay for the {} shift has been released", shift.role),
+                    Some(shift_id.clone()),
+                );
+            }
+            if escrow.refunded_amount > 0 {
+                push_notification(
+                    &escrow.business_id,
+                    NotificationType::PaymentProcessed,
+                    "Escrow refunded",
+                    format!("Unused escrow for the {} shift has been refunded", shift.role),
+                    Some(shift_id.clone()),
+                );
+            }
+            Ok(Some(escrow))
+        }
+        Err(error) => {
+            escrow.status = EscrowStatus::Funded;
+            escrow.last_error = Some(error.clone());
+            save_escrow(&escrow);
+            Err(error)
+        }
+    }
 }
 
-#[update]
-fn delete_shift(shift_id: ShiftId) -> Result<bool, String> {
-    let caller = api::caller();
-    
-    // Get existing shift
-    let existing_shift = SHIFTS.with(|shifts| {
-        shifts.borrow().get(&shift_id)
-            .ok_or_else(|| "Shift not found".to_string())
-    })?;
-    
-    // Verify caller owns this shift
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
-    
-    if existing_shift.business_id != user_profile.id {
-        return Err("Unauthorized: Cannot delete another business's shift".to_string());
+// Splits a seat's pay into (payout, advance repayment). A repayment the ledger
+// fee would swallow is not collected: it stays in the worker's payout and the
+// advance stays open.
+fn split_worker_share(worker_share: u64, owed: u64, fee: u64) -> (u64, u64) {
+    if owed > fee {
+        (worker_share - owed, owed)
+    } else {
+        (worker_share, 0)
+    }
+}
+
+// Pulls the estimated pay for a seat from the approving business into the
+// seat's escrow subaccount. The business must have granted this canister an
+// ICRC-2 allowance beforehand.
+async fn fund_shift_escrow(ledger: Principal, shift: &Shift, worker_id: &UserId, amount: u64) -> Result<Escrow, String> {
+    let key = escrow_key(&shift.id, worker_id);
+    if let Some(existing) = ESCROWS.with(|escrows| escrows.borrow().get(&key)) {
+        match existing.status {
+            EscrowStatus::Released | EscrowStatus::Refunded => {}
+            EscrowStatus::Funding | EscrowStatus::Settling => {
+                return Err("Escrow for this seat is already being processed".to_string());
+            }
+            EscrowStatus::Funded => {
+                return Err("Previous escrow for this seat has not been settled".to_string());
+            }
+        }
     }
     
-    SHIFTS.with(|shifts| {
-        shifts.borrow_mut().remove(&shift_id)
-    });
+    let subaccount = escrow_subaccount(&key);
+    let mut escrow = Escrow {
+        shift_id: shift.id.clone(),
+        business_id: shift.business_id.clone(),
+        business_principal: api::caller(),
+        worker_id: worker_id.clone(),
+        subaccount: subaccount.clone(),
+        amount,
+        status: EscrowStatus::Funding,
+        funding_block: None,
+        released_amount: 0,
+        release_block: None,
+        refunded_amount: 0,
+        refund_block: None,
+        created_at: get_current_time(),
+        settled_at: None,
+        last_error: None,
+        advance_repayment: None,
+        repayment_block: None,
+    };
+    save_escrow(&escrow);
     
-    Ok(true)
+    let from = Account { owner: api::caller(), subaccount: None };
+    let to = Account { owner: api::id(), subaccount: Some(subaccount) };
+    match ledger_transfer_from(ledger, from, to, amount).await {
+        Ok(block) => {
+            escrow.status = EscrowStatus::Funded;
+            escrow.funding_block = Some(block);
+            save_escrow(&escrow);
+            Ok(escrow)
+        }
+        Err(error) => {
+            ESCROWS.with(|escrows| escrows.borrow_mut().remove(&key));
+            Err(error)
+        }
+    }
 }
 
-#[query]
-fn get_shifts_by_business(business_id: UserId) -> Result<Vec<Shift>, String> {
-    let shifts: Vec<Shift> = SHIFTS.with(|shifts| {
-        shifts.borrow().iter()
-            .filter(|(_, shift)| shift.business_id == business_id)
-            .map(|(_, shift)| shift)
-            .collect()
-    });
-    
-    Ok(shifts)
+// A reschedule or raise must stay within what is escrowed for every seat still
+// to be paid; settlement caps the payout at the escrow, so anything above it
+// would silently go unpaid
+fn check_escrow_covers(shift: &Shift) -> Result<(), String> {
+    let estimated = to_ledger_units(&shift.pay_rate.for_duration_ms(shift.ends_at - shift.starts_at));
+    for escrow in shift_escrows(&shift.id) {
+        let unpaid_seat = seat_of(shift, &escrow.worker_id)
+            .is_some_and(|seat| seat.status != SeatStatus::Completed);
+        if escrow.status == EscrowStatus::Funded && unpaid_seat && estimated > escrow.amount {
+            return Err(format!(
+                "The escrow for worker {} covers {} ledger units but the shift would now pay {}; release the seat and approve it again to fund the difference",
+                escrow.worker_id, escrow.amount, estimated
+            ));
+        }
+    }
+    Ok(())
 }
 
-#[query]
-fn get_available_shifts(location_filter: Option<String>) -> Result<Vec<Shift>, String> {
-    let shifts: Vec<Shift> = SHIFTS.with(|shifts| {
-        shifts.borrow().iter()
-            .filter(|(_, shift)| {
-                shift.status == ShiftStatus::Open && 
-                location_filter.as_ref().map_or(true, |loc| shift.location.contains(loc))
-            })
-            .map(|(_, shift)| shift)
+// Whether a seat's escrow pays the worker (true) or goes back to the business
+// (false). Seats still working on an active shift cannot be settled yet.
+fn seat_earned_pay(shift: &Shift, worker_id: &UserId) -> Result<bool, String> {
+    match seat_of(shift, worker_id) {
+        Some(seat) if seat.status == SeatStatus::Completed => Ok(true),
+        Some(_) if is_active_shift(shift) => {
+            Err("Seat is still active; escrow cannot be settled yet".to_string())
+        }
+        _ => Ok(false),
+    }
+}
+
+// Settlement runs after the shift change has been committed, so a ledger
+// failure leaves the escrow Funded for a later settle_shift_escrow retry.
+async fn settle_escrow_after_change(shift_id: &ShiftId, worker_id: &UserId) {
+    if let Err(error) = settle_escrow(shift_id, worker_id).await {
+        ic_cdk::println!("Escrow settlement for shift {} seat {} deferred: {}", shift_id, worker_id, error);
+    }
+}
+
+// Settles every funded seat escrow of the shift, e.g. after a cancellation
+async fn settle_shift_escrows_after_change(shift_id: &ShiftId) {
+    let funded: Vec<UserId> = shift_escrows(shift_id).into_iter()
+        .filter(|escrow| escrow.status == EscrowStatus::Funded)
+        .map(|escrow| escrow.worker_id)
+        .collect();
+    for worker_id in funded {
+        settle_escrow_after_change(shift_id, &worker_id).await;
+    }
+}
+
+fn advance_terms() -> (u8, u16) {
+    let config = read_config();
+    (
+        config.advance_cap_percent.unwrap_or(DEFAULT_ADVANCE_CAP_PERCENT).min(100),
+        config.advance_fee_bps.unwrap_or(DEFAULT_ADVANCE_FEE_BPS),
+    )
+}
+
+fn advance_index_key(advance: &Advance) -> (UserId, u64, AdvanceId) {
+    (advance.worker_id.clone(), advance.requested_at.max(0) as u64, advance.id.clone())
+}
+
+fn save_advance(advance: &Advance) {
+    ADVANCES_BY_WORKER.with(|index| index.borrow_mut().insert(advance_index_key(advance), ()));
+    ADVANCES.with(|advances| advances.borrow_mut().insert(advance.id.clone(), advance.clone()));
+}
+
+fn worker_advances(worker_id: &UserId) -> Vec<Advance> {
+    let ids: Vec<AdvanceId> = ADVANCES_BY_WORKER.with(|index| {
+        index.borrow().range((worker_id.clone(), 0, String::new())..)
+            .take_while(|((owner, _, _), _)| owner == worker_id)
+            .map(|((_, _, id), _)| id)
             .collect()
     });
-    
-    Ok(shifts)
+    ADVANCES.with(|advances| {
+        let advances = advances.borrow();
+        ids.iter().filter_map(|id| advances.get(id)).collect()
+    })
 }
 
-// Shift Application Functions
-#[update]
-fn apply_to_shift(shift_id: ShiftId, message: Option<String>) -> Result<bool, String> {
-    let caller = api::caller();
-    
-    // Verify caller is a worker
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
-    
-    if user_profile.user_type != UserType::Worker {
-        return Err("Only workers can apply to shifts".to_string());
+fn index_advances() {
+    ADVANCES_BY_WORKER.with(|index| index.borrow_mut().clear_new());
+    let advances: Vec<Advance> = ADVANCES.with(|advances| advances.borrow().iter().map(|(_, advance)| advance).collect());
+    for advance in &advances {
+        ADVANCES_BY_WORKER.with(|index| index.borrow_mut().insert(advance_index_key(advance), ()));
+    }
+}
+
+// Advances still owed, i.e. neither repaid nor failed
+fn open_advance_allocations(worker_id: &UserId) -> Vec<(AdvanceStatus, AdvanceAllocation)> {
+    worker_advances(worker_id).into_iter()
+        .filter(|advance| matches!(advance.status, AdvanceStatus::Pending | AdvanceStatus::Paid))
+        .flat_map(|advance| {
+            let status = advance.status.clone();
+            advance.allocations.into_iter()
+                .filter(|allocation| allocation.repaid_at.is_none())
+                .map(move |allocation| (status.clone(), allocation))
+        })
+        .collect()
+}
+
+// Advance debt drawn against a work history entry, to be netted out of its pay.
+// Refuses while an advance payout against the entry is still in flight.
+fn outstanding_advance_debt(entry: &WorkHistory) -> Result<Money, String> {
+    let mut amount_minor = 0u64;
+    for (status, allocation) in open_advance_allocations(&entry.worker_id) {
+        if allocation.work_history_id != entry.id {
+            continue;
+        }
+        if status == AdvanceStatus::Pending {
+            return Err("An advance against this work is still being paid out".to_string());
+        }
+        amount_minor += allocation.amount_minor;
     }
+    Ok(Money { amount_minor, currency: entry.pay_earned.currency.clone() })
+}
+
+// Completed, unpaid, unrevoked entries in the given currency, oldest first,
+// with how much more advance debt each can carry. Entries whose escrow
+// settlement has already fixed its repayment cannot take new advances.
+fn advance_capacity(worker_id: &UserId, currency: &str) -> Vec<(WorkHistory, u64)> {
+    let (cap_percent, _) = advance_terms();
+    let allocations = open_advance_allocations(worker_id);
     
-    // Get shift and verify it's open
-    let mut shift = SHIFTS.with(|shifts| {
-        shifts.borrow().get(&shift_id)
-            .ok_or_else(|| "Shift not found".to_string())
-    })?;
+    // Only work the business has confirmed counts, and never more than is
+    // escrowed for it, so the pool is not exposed to unapproved earnings
+    let mut entries: Vec<(WorkHistory, u64)> = worker_history(worker_id).into_iter()
+        .filter(|entry| entry.settled_at.is_none())
+        .filter(|entry| entry.confirmed_at.is_some())
+        .filter(|entry| entry.pay_earned.currency == currency)
+        .filter(|entry| !is_revoked(&work_history_credential_id(&entry.id)))
+        .filter_map(|entry| {
+            let escrow = ESCROWS.with(|escrows| escrows.borrow().get(&escrow_key(&entry.shift_id, &entry.worker_id)));
+            if escrow.as_ref().is_some_and(|escrow| escrow.advance_repayment.is_some()) {
+                return None;
+            }
+            let escrowed = escrow.filter(|escrow| escrow.status == EscrowStatus::Funded)
+                .map(|escrow| from_ledger_units(escrow.amount, currency));
+            let base = advanceable_earnings(&entry, escrowed);
+            Some((entry, base))
+        })
+        .collect();
+    entries.sort_by_key(|(entry, _)| entry.completed_at);
     
-    if shift.status != ShiftStatus::Open {
-        return Err("Shift is not open for applications".to_string());
+    entries.into_iter()
+        .map(|(entry, base)| {
+            let cap = (base as u128 * cap_percent as u128 / 100) as u64;
+            let drawn: u64 = allocations.iter()
+                .filter(|(_, allocation)| allocation.work_history_id == entry.id)
+                .map(|(_, allocation)| allocation.amount_minor)
+                .sum();
+            let remaining = cap.saturating_sub(drawn);
+            (entry, remaining)
+        })
+        .collect()
+}
+
+// The part of an entry's pay an advance may be drawn against: nothing until
+// the business confirms it, and at most the escrowed amount when funded
+fn advanceable_earnings(entry: &WorkHistory, escrowed_minor: Option<u64>) -> u64 {
+    if entry.confirmed_at.is_none() {
+        return 0;
     }
+    escrowed_minor.map_or(entry.pay_earned.amount_minor, |escrowed| entry.pay_earned.amount_minor.min(escrowed))
+}
+
+fn advance_balance(worker_id: &UserId, currency: &str) -> AdvanceBalance {
+    let (cap_percent, fee_bps) = advance_terms();
+    let capacity = advance_capacity(worker_id, currency);
+    let outstanding = worker_advances(worker_id).into_iter()
+        .filter(|advance| advance.amount.currency == currency)
+        .filter(|advance| matches!(advance.status, AdvanceStatus::Pending | AdvanceStatus::Paid))
+        .flat_map(|advance| advance.allocations)
+        .filter(|allocation| allocation.repaid_at.is_none())
+        .map(|allocation| allocation.amount_minor)
+        .sum();
     
-    // Check if worker already applied
-    if shift.applicants.contains(&user_profile.id) {
-        return Err("Already applied to this shift".to_string());
+    AdvanceBalance {
+        currency: currency.to_string(),
+        unsettled_earnings: capacity.iter().map(|(entry, _)| entry.pay_earned.amount_minor).sum(),
+        outstanding,
+        available: capacity.iter().map(|(_, remaining)| remaining).sum(),
+        cap_percent,
+        fee_bps,
     }
+}
+
+// Fee on an advance, rounded up to the next minor unit
+fn advance_fee(amount: &Money, fee_bps: u16) -> Money {
+    let fee = (amount.amount_minor as u128 * fee_bps as u128).div_ceil(10_000);
+    Money { amount_minor: fee as u64, currency: amount.currency.clone() }
+}
+
+// Called once the business has paid for an entry: its allocations are settled,
+// and advances with nothing left outstanding are marked repaid
+fn mark_advances_repaid(entry: &WorkHistory, now: i64) {
+    let affected: Vec<Advance> = worker_advances(&entry.worker_id).into_iter()
+        .filter(|advance| advance.status == AdvanceStatus::Paid)
+        .filter(|advance| advance.allocations.iter().any(|allocation| {
+            allocation.work_history_id == entry.id && allocation.repaid_at.is_none()
+        }))
+        .collect();
     
-    // Add application
-    let application = ShiftApplication {
-        shift_id: shift_id.clone(),
-        worker_id: user_profile.id.clone(),
-        applied_at: get_current_time(),
-        message,
-        status: ApplicationStatus::Pending,
-    };
-    
-    let app_key = format!("{}_{}", shift_id, user_profile.id);
-    APPLICATIONS.with(|apps| {
-        apps.borrow_mut().insert(app_key, application)
+    for mut advance in affected {
+        for allocation in advance.allocations.iter_mut() {
+            if allocation.work_history_id == entry.id {
+                allocation.repaid_at = Some(now);
+            }
+        }
+        if advance.allocations.iter().all(|allocation| allocation.repaid_at.is_some()) {
+            advance.status = AdvanceStatus::Repaid;
+            advance.repaid_at = Some(now);
+        }
+        save_advance(&advance);
+    }
+}
+
+fn read_config() -> CanisterConfig {
+    CONFIG.with(|config| config.borrow().get().clone())
+}
+
+fn update_config(f: impl FnOnce(&mut CanisterConfig)) {
+    CONFIG.with(|config| {
+        let mut current = config.borrow().get().clone();
+        f(&mut current);
+        config.borrow_mut().set(current).expect("Failed to persist canister config");
     });
+}
+
+fn ecdsa_key_id() -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: read_config().ecdsa_key_name,
+    }
+}
+
+fn signing_key() -> Option<SigningKey> {
+    let config = read_config();
+    config.ecdsa_public_key.map(|public_key| SigningKey {
+        canister_id: api::id(),
+        curve: "secp256k1".to_string(),
+        key_name: config.ecdsa_key_name,
+        derivation_path: vec![String::from_utf8_lossy(DID_DERIVATION_PATH).to_string()],
+        public_key: to_hex(&public_key),
+    })
+}
+
+async fn ensure_signing_public_key() -> Result<Vec<u8>, String> {
+    if let Some(public_key) = read_config().ecdsa_public_key {
+        return Ok(public_key);
+    }
     
-    // Update shift with new applicant
-    shift.applicants.push(user_profile.id);
-    shift.updated_at = get_current_time();
+    let (response,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path: vec![DID_DERIVATION_PATH.to_vec()],
+        key_id: ecdsa_key_id(),
+    })
+    .await
+    .map_err(|(code, msg)| format!("Failed to fetch signing key: {:?} {}", code, msg))?;
     
-    SHIFTS.with(|shifts| {
-        shifts.borrow_mut().insert(shift_id, shift)
-    });
+    update_config(|config| config.ecdsa_public_key = Some(response.public_key.clone()));
+    Ok(response.public_key)
+}
+
+async fn sign_digest(digest: [u8; 32]) -> Result<String, String> {
+    let (response,) = sign_with_ecdsa(SignWithEcdsaArgument {
+        message_hash: digest.to_vec(),
+        derivation_path: vec![DID_DERIVATION_PATH.to_vec()],
+        key_id: ecdsa_key_id(),
+    })
+    .await
+    .map_err(|(code, msg)| format!("Failed to sign: {:?} {}", code, msg))?;
     
-    Ok(true)
+    Ok(to_hex(&response.signature))
 }
 
-#[query]
-fn get_shift_applications(shift_id: ShiftId) -> Vec<ShiftApplication> {
-    APPLICATIONS.with(|apps| {
-        apps.borrow().iter()
-            .filter(|(key, _)| key.starts_with(&shift_id))
-            .map(|(_, app)| app)
-            .collect()
+// Paging
+fn page_limit(limit: Option<u32>) -> usize {
+    limit.map_or(DEFAULT_PAGE_SIZE, |limit| (limit as usize).clamp(1, MAX_PAGE_SIZE))
+}
+
+// Cursors are "<sort value>:<id>" of the last item returned, so a page picks
+// up where the previous one ended even if records were added in between
+fn parse_cursor(cursor: &str) -> Result<(i128, String), String> {
+    let (value, id) = cursor.split_once(':')
+        .ok_or_else(|| "Invalid cursor".to_string())?;
+    let value = value.parse().map_err(|_| "Invalid cursor".to_string())?;
+    Ok((value, id.to_string()))
+}
+
+fn paginate<T>(
+    items: Vec<T>,
+    page: &PageRequest,
+    default_direction: SortDirection,
+    sort_value: impl Fn(&T) -> (i128, String),
+) -> Result<Page<T>, String> {
+    let limit = page_limit(page.limit);
+    let direction = page.direction.unwrap_or(default_direction);
+    let total = items.len() as u64;
+
+    // Ties on the sort value are broken by id so the order is total
+    let mut keyed: Vec<((i128, String), T)> = items.into_iter()
+        .map(|item| (sort_value(&item), item))
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if direction == SortDirection::Desc {
+        keyed.reverse();
+    }
+
+    if let Some(cursor) = &page.cursor {
+        let after = parse_cursor(cursor)?;
+        keyed.retain(|(key, _)| match direction {
+            SortDirection::Asc => *key > after,
+            SortDirection::Desc => *key < after,
+        });
+    }
+
+    let next_cursor = if keyed.len() > limit {
+        keyed.get(limit - 1).map(|((value, id), _)| format!("{}:{}", value, id))
+    } else {
+        None
+    };
+    keyed.truncate(limit);
+
+    Ok(Page {
+        items: keyed.into_iter().map(|(_, item)| item).collect(),
+        next_cursor,
+        total: Some(total),
     })
 }
 
-#[update]
-fn approve_application(shift_id: ShiftId, worker_id: UserId) -> Result<bool, String> {
-    let caller = api::caller();
+// Pages one owner's slice of an (owner, sort value, id) index in key order,
+// reading only the entries of the requested page. Cursors have the same
+// "<sort value>:<id>" form as paginate's.
+#[allow(clippy::type_complexity)]
+fn page_index<V>(
+    index: &IndexMap<(String, u64, String), V>,
+    owner: &str,
+    page: &PageRequest,
+    default_direction: SortDirection,
+) -> Result<(Vec<((String, u64, String), V)>, Option<String>), String>
+where
+    V: ic_stable_structures::Storable,
+{
+    use std::ops::Bound;
     
-    // Verify caller owns the shift
-    let mut shift = SHIFTS.with(|shifts| {
-        shifts.borrow().get(&shift_id)
-            .ok_or_else(|| "Shift not found".to_string())
-    })?;
+    let limit = page_limit(page.limit);
+    let direction = page.direction.unwrap_or(default_direction);
+    let after = match &page.cursor {
+        Some(cursor) => {
+            let (value, id) = parse_cursor(cursor)?;
+            let value = u64::try_from(value).map_err(|_| "Invalid cursor".to_string())?;
+            Some((owner.to_string(), value, id))
+        }
+        None => None,
+    };
     
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
+    // Sort values are timestamps, so none reaches u64::MAX
+    let first = (owner.to_string(), 0, String::new());
+    let last = (owner.to_string(), u64::MAX, String::new());
+    let (start, end) = match (direction, after) {
+        (SortDirection::Asc, Some(after)) => (Bound::Excluded(after), Bound::Excluded(last)),
+        (SortDirection::Desc, Some(after)) => (Bound::Included(first), Bound::Excluded(after)),
+        (_, None) => (Bound::Included(first), Bound::Excluded(last)),
+    };
+    let range = index.range((start, end));
+    let mut entries: Vec<((String, u64, String), V)> = match direction {
+        SortDirection::Asc => range.take(limit + 1).collect(),
+        SortDirection::Desc => range.rev().take(limit + 1).collect(),
+    };
     
-    if shift.business_id != user_profile.id {
-        return Err("Unauthorized: Cannot approve applications for another business's shift".to_string());
+    let next_cursor = if entries.len() > limit {
+        entries.truncate(limit);
+        entries.last().map(|((_, value, id), _)| format!("{}:{}", value, id))
+    } else {
+        None
+    };
+    Ok((entries, next_cursor))
+}
+
+fn shift_sort_value(shift: &Shift, sort_by: SortKey) -> i128 {
+    match sort_by {
+        SortKey::Date => shift.starts_at as i128,
+        SortKey::PayRate => shift.pay_rate.amount_minor as i128,
+        SortKey::CreatedAt => shift.created_at as i128,
     }
+}
+
+fn paginate_shifts(shifts: Vec<Shift>, page: &PageRequest) -> Result<Page<Shift>, String> {
+    let sort_by = page.sort_by.unwrap_or(SortKey::Date);
+    paginate(shifts, page, SortDirection::Asc, |shift| {
+        (shift_sort_value(shift, sort_by), shift.id.clone())
+    })
+}
+
+// Geospatial
+fn validate_geo_point(point: &GeoPoint) -> Result<(), String> {
+    if !point.latitude.is_finite() || !(-90.0..=90.0).contains(&point.latitude) {
+        return Err("Latitude must be between -90 and 90".to_string());
+    }
+    if !point.longitude.is_finite() || !(-180.0..=180.0).contains(&point.longitude) {
+        return Err("Longitude must be between -180 and 180".to_string());
+    }
+    Ok(())
+}
+
+fn geohash(point: &GeoPoint, precision: usize) -> String {
+    let mut latitude = (-90.0, 90.0);
+    let mut longitude = (-180.0, 180.0);
+    let mut hash = String::with_capacity(precision);
+    let (mut bits, mut index, mut even) = (0, 0, true);
+    while hash.len() < precision {
+        // Bits alternate between longitude and latitude, longitude first
+        let (range, value): (&mut (f64, f64), f64) = if even {
+            (&mut longitude, point.longitude)
+        } else {
+            (&mut latitude, point.latitude)
+        };
+        let mid = (range.0 + range.1) / 2.0;
+        index <<= 1;
+        if value >= mid {
+            index |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+        even = !even;
+        bits += 1;
+        if bits == 5 {
+            hash.push(GEOHASH_ALPHABET[index] as char);
+            bits = 0;
+            index = 0;
+        }
+    }
+    hash
+}
+
+// Width and height in degrees of a geohash cell
+fn geohash_cell_size(precision: usize) -> (f64, f64) {
+    let bits = 5 * precision as i32;
+    let longitude_bits = (bits + 1) / 2;
+    let latitude_bits = bits / 2;
+    (360.0 / 2f64.powi(longitude_bits), 180.0 / 2f64.powi(latitude_bits))
+}
+
+// Geohash cells covering the bounding box of a circle, at the finest
+// precision that keeps the count within MAX_GEO_CELLS
+fn geohash_cells(center: &GeoPoint, radius_km: f64) -> Vec<String> {
+    let latitude_delta = (radius_km / EARTH_RADIUS_KM).to_degrees();
+    let min_latitude = (center.latitude - latitude_delta).max(-90.0);
+    let max_latitude = (center.latitude + latitude_delta).min(90.0);
+    // A degree of longitude shrinks towards the poles; close to one the box
+    // spans every longitude
+    let narrowest = min_latitude.abs().max(max_latitude.abs()).to_radians().cos();
+    let longitude_delta = if narrowest < 1e-6 { 180.0 } else { (latitude_delta / narrowest).min(180.0) };
+    let min_longitude = center.longitude - longitude_delta;
+    let max_longitude = center.longitude + longitude_delta;
     
-    // Update application status
-    let app_key = format!("{}_{}", shift_id, worker_id);
-    APPLICATIONS.with(|apps| {
-        if let Some(mut app) = apps.borrow_mut().get(&app_key) {
-            app.status = ApplicationStatus::Approved;
-            apps.borrow_mut().insert(app_key, app);
+    for precision in (1..=GEOHASH_PRECISION).rev() {
+        let (width, height) = geohash_cell_size(precision);
+        let columns = ((max_longitude - min_longitude) / width).ceil() as usize + 1;
+        let rows = ((max_latitude - min_latitude) / height).ceil() as usize + 1;
+        if columns * rows > MAX_GEO_CELLS && precision > 1 {
+            continue;
+        }
+        
+        // Sample points no further apart than a cell, so every cell the box
+        // touches contains at least one
+        let mut cells = std::collections::BTreeSet::new();
+        for row in 0..rows {
+            let latitude = (min_latitude + row as f64 * height).min(max_latitude);
+            for column in 0..columns {
+                let longitude = (min_longitude + column as f64 * width).min(max_longitude);
+                let point = GeoPoint {
+                    latitude,
+                    longitude: (longitude + 180.0).rem_euclid(360.0) - 180.0,
+                };
+                cells.insert(geohash(&point, precision));
+            }
         }
+        return cells.into_iter().collect();
+    }
+    Vec::new()
+}
+
+// Great-circle distance (haversine)
+fn distance_km(a: &GeoPoint, b: &GeoPoint) -> f64 {
+    let (latitude_a, latitude_b) = (a.latitude.to_radians(), b.latitude.to_radians());
+    let half_latitude = (latitude_b - latitude_a) / 2.0;
+    let half_longitude = (b.longitude - a.longitude).to_radians() / 2.0;
+    let h = half_latitude.sin().powi(2)
+        + latitude_a.cos() * latitude_b.cos() * half_longitude.sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().min(1.0).asin()
+}
+
+// Open shifts with coordinates in any of the cells, deduplicated
+fn shifts_in_cells(cells: &[String]) -> Vec<Shift> {
+    let mut ids: Vec<ShiftId> = SHIFTS_BY_GEOHASH.with(|index| {
+        let index = index.borrow();
+        cells.iter()
+            .flat_map(|cell| {
+                index.range((cell.clone(), String::new())..)
+                    .take_while(|((hash, _), _)| hash.starts_with(cell.as_str()))
+                    .map(|((_, id), _)| id)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
     });
-    
-    // Update shift
-    shift.assigned_worker = Some(worker_id);
-    shift.status = ShiftStatus::Approved;
-    shift.updated_at = get_current_time();
-    
+    ids.sort();
+    ids.dedup();
     SHIFTS.with(|shifts| {
-        shifts.borrow_mut().insert(shift_id, shift)
-    });
-    
-    Ok(true)
+        let shifts = shifts.borrow();
+        ids.iter().filter_map(|id| shifts.get(id)).collect()
+    })
 }
 
-#[update]
-fn reject_application(shift_id: ShiftId, worker_id: UserId) -> Result<bool, String> {
-    let caller = api::caller();
-    
-    // Verify caller owns the shift
-    let shift = SHIFTS.with(|shifts| {
-        shifts.borrow().get(&shift_id)
-            .ok_or_else(|| "Shift not found".to_string())
-    })?;
-    
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
-    
-    if shift.business_id != user_profile.id {
-        return Err("Unauthorized: Cannot reject applications for another business's shift".to_string());
+// Index Maintenance
+fn shift_status_code(status: &ShiftStatus) -> u8 {
+    match status {
+        ShiftStatus::Draft => 0,
+        ShiftStatus::Open => 1,
+        ShiftStatus::Claimed => 2,
+        ShiftStatus::Approved => 3,
+        ShiftStatus::InProgress => 4,
+        ShiftStatus::Completed => 5,
+        ShiftStatus::Cancelled => 6,
+        ShiftStatus::Expired => 7,
     }
-    
-    // Update application status
-    let app_key = format!("{}_{}", shift_id, worker_id);
-    APPLICATIONS.with(|apps| {
-        if let Some(mut app) = apps.borrow_mut().get(&app_key) {
-            app.status = ApplicationStatus::Rejected;
-            apps.borrow_mut().insert(app_key, app);
-        }
-    });
-    
-    Ok(true)
 }
 
-// Work History & DID Functions
-#[update]
-fn create_work_history(work_history: WorkHistory) -> Result<bool, String> {
-    let caller = api::caller();
-    
-    // Verify caller is the business who created the shift
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
-    
-    if user_profile.id != work_history.business_id {
-        return Err("Unauthorized: Only the business can create work history".to_string());
+fn shift_status_key(shift: &Shift) -> (u8, u64, ShiftId) {
+    (shift_status_code(&shift.status), shift.starts_at.max(0) as u64, shift.id.clone())
+}
+
+// Only open shifts are placed on the map
+fn shift_geohash_key(shift: &Shift) -> Option<(String, ShiftId)> {
+    if shift.status != ShiftStatus::Open {
+        return None;
     }
-    
-    WORK_HISTORY.with(|history| {
-        history.borrow_mut().insert(work_history.id.clone(), work_history.clone())
-    });
-    
-    // Update worker's DID document
-    DID_DOCUMENTS.with(|docs| {
-        if let Some(mut did_doc) = docs.borrow_mut().get(&work_history.worker_id) {
-            did_doc.work_history.push(work_history.id.clone());
-            did_doc.total_shifts += 1;
-            did_doc.updated_at = get_current_time();
-            docs.borrow_mut().insert(work_history.worker_id.clone(), did_doc);
+    shift.coordinates.as_ref().map(|point| (geohash(point, GEOHASH_PRECISION), shift.id.clone()))
+}
+
+// Keys of the shift in SCHEDULE_BY_WORKER: one per worker still due on it
+fn schedule_keys(shift: &Shift) -> Vec<(UserId, u64, ShiftId)> {
+    if !is_active_shift(shift) {
+        return Vec::new();
+    }
+    shift.seats.iter()
+        .filter(|seat| matches!(seat.status, SeatStatus::Assigned | SeatStatus::CheckedIn))
+        .map(|seat| (seat.worker_id.clone(), shift.starts_at.max(0) as u64, shift.id.clone()))
+        .collect()
+}
+
+// Every shift write goes through here so the indexes stay in step
+fn save_shift(shift: &Shift) {
+    let previous = SHIFTS.with(|shifts| shifts.borrow_mut().insert(shift.id.clone(), shift.clone()));
+    if let Some(previous) = &previous {
+        SHIFTS_BY_STATUS.with(|index| index.borrow_mut().remove(&shift_status_key(previous)));
+        if let Some(key) = shift_geohash_key(previous) {
+            SHIFTS_BY_GEOHASH.with(|index| index.borrow_mut().remove(&key));
         }
+        for seat in &previous.seats {
+            SHIFTS_BY_WORKER.with(|index| index.borrow_mut().remove(&(seat.worker_id.clone(), shift.id.clone())));
+        }
+        for key in schedule_keys(previous) {
+            SCHEDULE_BY_WORKER.with(|index| index.borrow_mut().remove(&key));
+        }
+    }
+    SHIFTS_BY_STATUS.with(|index| index.borrow_mut().insert(shift_status_key(shift), ()));
+    if let Some(key) = shift_geohash_key(shift) {
+        SHIFTS_BY_GEOHASH.with(|index| index.borrow_mut().insert(key, ()));
+    }
+    for seat in &shift.seats {
+        SHIFTS_BY_WORKER.with(|index| index.borrow_mut().insert((seat.worker_id.clone(), shift.id.clone()), ()));
+    }
+    for key in schedule_keys(shift) {
+        SCHEDULE_BY_WORKER.with(|index| index.borrow_mut().insert(key, ()));
+    }
+    SHIFTS_BY_BUSINESS.with(|index| {
+        index.borrow_mut().insert((shift.business_id.clone(), shift.id.clone()), ())
     });
-    
-    Ok(true)
+    if let Some(event_id) = &shift.event_id {
+        SHIFTS_BY_EVENT.with(|index| index.borrow_mut().insert((event_id.clone(), shift.id.clone()), ()));
+    }
 }
 
-#[query]
-fn get_worker_history(worker_id: UserId) -> Result<Vec<WorkHistory>, String> {
-    let history: Vec<WorkHistory> = WORK_HISTORY.with(|history| {
-        history.borrow().iter()
-            .filter(|(_, entry)| entry.worker_id == worker_id)
-            .map(|(_, entry)| entry)
-            .collect()
+fn location_key(location: &str) -> String {
+    location.trim().to_ascii_lowercase()
+}
+
+fn save_user_profile(profile: &UserProfile) {
+    let previous = USER_PROFILES.with(|profiles| {
+        profiles.borrow_mut().insert(profile.owner_principal, profile.clone())
     });
-    
-    Ok(history)
+    if let Some(previous) = previous.filter(|previous| previous.user_type == UserType::Worker) {
+        WORKERS_BY_LOCATION.with(|index| {
+            index.borrow_mut().remove(&(location_key(&previous.location), previous.id))
+        });
+    }
+    if profile.user_type == UserType::Worker {
+        WORKERS_BY_LOCATION.with(|index| {
+            index.borrow_mut().insert((location_key(&profile.location), profile.id.clone()), ())
+        });
+    }
 }
 
-#[update]
-fn create_rating(rating: Rating) -> Result<bool, String> {
-    let caller = api::caller();
-    
-    // Verify caller is the business who created the shift
-    let user_profile = USER_PROFILES.with(|profiles| {
-        profiles.borrow().get(&caller)
-            .ok_or_else(|| "User profile not found".to_string())
-    })?;
-    
-    if user_profile.id != rating.business_id {
-        return Err("Unauthorized: Only the business can create ratings".to_string());
+fn worker_geohash_key(profile: &WorkerProfile) -> Option<(String, UserId)> {
+    profile.home_area.as_ref().map(|point| (geohash(point, GEOHASH_PRECISION), profile.user_id.clone()))
+}
+
+fn save_worker_profile(profile: &WorkerProfile) {
+    let previous = WORKER_PROFILES.with(|profiles| {
+        profiles.borrow_mut().insert(profile.user_id.clone(), profile.clone())
+    });
+    if let Some(key) = previous.as_ref().and_then(worker_geohash_key) {
+        WORKERS_BY_GEOHASH.with(|index| index.borrow_mut().remove(&key));
     }
-    
-    RATINGS.with(|ratings| {
-        ratings.borrow_mut().insert(rating.id.clone(), rating.clone())
+    if let Some(key) = worker_geohash_key(profile) {
+        WORKERS_BY_GEOHASH.with(|index| index.borrow_mut().insert(key, ()));
+    }
+}
+
+fn remove_shift(shift: &Shift) {
+    SHIFTS.with(|shifts| shifts.borrow_mut().remove(&shift.id));
+    SHIFTS_BY_STATUS.with(|index| index.borrow_mut().remove(&shift_status_key(shift)));
+    if let Some(key) = shift_geohash_key(shift) {
+        SHIFTS_BY_GEOHASH.with(|index| index.borrow_mut().remove(&key));
+    }
+    for seat in &shift.seats {
+        SHIFTS_BY_WORKER.with(|index| index.borrow_mut().remove(&(seat.worker_id.clone(), shift.id.clone())));
+    }
+    for key in schedule_keys(shift) {
+        SCHEDULE_BY_WORKER.with(|index| index.borrow_mut().remove(&key));
+    }
+    SHIFTS_BY_BUSINESS.with(|index| {
+        index.borrow_mut().remove(&(shift.business_id.clone(), shift.id.clone()))
     });
-    
-    // Update worker's DID document and average rating
-    DID_DOCUMENTS.with(|docs| {
-        if let Some(mut did_doc) = docs.borrow_mut().get(&rating.worker_id) {
-            did_doc.ratings.push(rating.id.clone());
-            
-            // Recalculate average rating
-            let all_ratings: Vec<Rating> = RATINGS.with(|ratings| {
-                ratings.borrow().iter()
-                    .filter(|(_, r)| r.worker_id == rating.worker_id)
-                    .map(|(_, r)| r)
-                    .collect()
-            });
-            
-            did_doc.average_rating = calculate_average_rating(&all_ratings);
-            did_doc.updated_at = get_current_time();
-            docs.borrow_mut().insert(rating.worker_id.clone(), did_doc);
-        }
+    if let Some(event_id) = &shift.event_id {
+        SHIFTS_BY_EVENT.with(|index| index.borrow_mut().remove(&(event_id.clone(), shift.id.clone())));
+    }
+}
+
+fn save_event(event: &Event) {
+    EVENTS.with(|events| events.borrow_mut().insert(event.id.clone(), event.clone()));
+    EVENTS_BY_BUSINESS.with(|index| {
+        index.borrow_mut().insert((event.business_id.clone(), event.id.clone()), ())
     });
-    
-    // Update worker profile with new rating
-    WORKER_PROFILES.with(|profiles| {
-        if let Some(mut profile) = profiles.borrow_mut().get(&rating.worker_id) {
-            let all_ratings: Vec<Rating> = RATINGS.with(|ratings| {
-                ratings.borrow().iter()
-                    .filter(|(_, r)| r.worker_id == rating.worker_id)
-                    .map(|(_, r)| r)
-                    .collect()
-            });
-            profile.average_rating = calculate_average_rating(&all_ratings);
-            profiles.borrow_mut().insert(rating.worker_id.clone(), profile);
-        }
+}
+
+fn save_work_history(entry: &WorkHistory) {
+    WORK_HISTORY.with(|history| history.borrow_mut().insert(entry.id.clone(), entry.clone()));
+    WORK_HISTORY_BY_WORKER.with(|index| {
+        index.borrow_mut().insert((entry.worker_id.clone(), entry.id.clone()), ())
     });
-    
-    Ok(true)
 }
 
-#[query]
-fn get_worker_ratings(worker_id: UserId) -> Result<Vec<Rating>, String> {
-    let ratings: Vec<Rating> = RATINGS.with(|ratings| {
-        ratings.borrow().iter()
-            .filter(|(_, rating)| rating.worker_id == worker_id)
-            .map(|(_, rating)| rating)
-            .collect()
+fn save_rating(rating: &Rating) {
+    RATINGS.with(|ratings| ratings.borrow_mut().insert(rating.id.clone(), rating.clone()));
+    RATINGS_BY_WORKER.with(|index| {
+        index.borrow_mut().insert((rating.worker_id.clone(), rating.id.clone()), ())
     });
-    
-    Ok(ratings)
 }
 
-#[query]
-fn get_worker_did(worker_id: UserId) -> Result<DIDDocument, String> {
-    DID_DOCUMENTS.with(|docs| {
-        docs.borrow().get(&worker_id)
-            .ok_or_else(|| "DID document not found".to_string())
-    })
+// Shifts with the given status, soonest start first
+fn shifts_with_status(status: &ShiftStatus) -> Vec<Shift> {
+    shifts_with_status_between(status, None, None)
 }
 
-#[query]
-fn export_worker_did(worker_id: UserId) -> Result<DIDDocument, String> {
-    // Same as get_worker_did but intended for portability
-    get_worker_did(worker_id)
+// Shifts in a status starting within [from, to], read as a single index range
+fn shifts_with_status_between(status: &ShiftStatus, from: Option<i64>, to: Option<i64>) -> Vec<Shift> {
+    let code = shift_status_code(status);
+    let from = from.map_or(0, |from| from.max(0) as u64);
+    let to = to.map_or(u64::MAX, |to| to.max(0) as u64);
+    let ids: Vec<ShiftId> = SHIFTS_BY_STATUS.with(|index| {
+        index.borrow().range((code, from, String::new())..)
+            .take_while(|((key_code, starts_at, _), _)| *key_code == code && *starts_at <= to)
+            .map(|((_, _, id), _)| id)
+            .collect()
+    });
+    SHIFTS.with(|shifts| {
+        let shifts = shifts.borrow();
+        ids.iter().filter_map(|id| shifts.get(id)).collect()
+    })
 }
 
-// Notification Functions
-#[update]
-fn create_notification(notification: Notification) -> Result<bool, String> {
-    NOTIFICATIONS.with(|notifications| {
-        notifications.borrow_mut().insert(notification.id.clone(), notification)
+fn business_shifts(business_id: &UserId) -> Vec<Shift> {
+    let ids: Vec<ShiftId> = SHIFTS_BY_BUSINESS.with(|index| {
+        index.borrow().range((business_id.clone(), String::new())..)
+            .take_while(|((owner, _), _)| owner == business_id)
+            .map(|((_, id), _)| id)
+            .collect()
     });
-    Ok(true)
+    SHIFTS.with(|shifts| {
+        let shifts = shifts.borrow();
+        ids.iter().filter_map(|id| shifts.get(id)).collect()
+    })
 }
 
-#[query]
-fn get_user_notifications(user_id: UserId) -> Result<Vec<Notification>, String> {
-    let notifications: Vec<Notification> = NOTIFICATIONS.with(|notifications| {
-        notifications.borrow().iter()
-            .filter(|(_, notif)| notif.user_id == user_id)
-            .map(|(_, notif)| notif)
+// Shifts the worker holds an unfinished seat on, soonest first
+fn committed_shifts(worker_id: &UserId) -> Vec<Shift> {
+    let ids: Vec<ShiftId> = SCHEDULE_BY_WORKER.with(|index| {
+        index.borrow().range((worker_id.clone(), 0, String::new())..)
+            .take_while(|((owner, _, _), _)| owner == worker_id)
+            .map(|((_, _, id), _)| id)
             .collect()
     });
-    
-    Ok(notifications)
+    SHIFTS.with(|shifts| {
+        let shifts = shifts.borrow();
+        ids.iter().filter_map(|id| shifts.get(id)).collect()
+    })
 }
 
-#[update]
-fn mark_notification_read(notification_id: String) -> Result<bool, String> {
-    NOTIFICATIONS.with(|notifications| {
-        if let Some(mut notif) = notifications.borrow_mut().get(&notification_id) {
-            notif.is_read = true;
-            notifications.borrow_mut().insert(notification_id, notif);
-            Ok(true)
-        } else {
-            Err("Notification not found".to_string())
-        }
+fn committed_schedule(worker_id: &UserId, page: &PageRequest) -> Result<Page<Shift>, String> {
+    let (keys, next_cursor) = SCHEDULE_BY_WORKER.with(|index| {
+        page_index(&index.borrow(), worker_id, page, SortDirection::Asc)
+    })?;
+    let items = SHIFTS.with(|shifts| {
+        let shifts = shifts.borrow();
+        keys.iter().filter_map(|((_, _, id), _)| shifts.get(id)).collect()
+    });
+    Ok(Page { items, next_cursor, total: None })
+}
+
+// A committed shift that overlaps `shift` or sits closer to it than the
+// configured minimum gap
+fn schedule_conflict(worker_id: &UserId, shift: &Shift) -> Option<Shift> {
+    let gap_ms = read_config().min_shift_gap_minutes.unwrap_or(DEFAULT_MIN_SHIFT_GAP_MINUTES) as i64 * 60_000;
+    committed_shifts(worker_id).into_iter().find(|other| {
+        other.id != shift.id
+            && shift.starts_at < other.ends_at + gap_ms
+            && other.starts_at < shift.ends_at + gap_ms
     })
 }
 
-// Utility Functions
-#[query]
-fn get_caller_principal() -> Principal {
-    api::caller()
+fn schedule_conflict_error(subject: &str, conflict: &Shift) -> String {
+    format!(
+        "{} already booked on shift {} ({}, {} to {}), which overlaps this shift or leaves less than the minimum gap between shifts",
+        subject,
+        conflict.id,
+        conflict.role,
+        format_rfc3339(conflict.starts_at),
+        format_rfc3339(conflict.ends_at),
+    )
 }
 
-#[query]
-fn health_check() -> bool {
-    true
+// Labor Rules
+fn normalize_jurisdiction(jurisdiction: &str) -> String {
+    jurisdiction.trim().to_uppercase()
 }
 
-// Canister initialization
-#[ic_cdk::init]
-fn init() {
-    // Canister initialized
+fn validate_jurisdiction(jurisdiction: &str) -> Result<(), String> {
+    let valid = !jurisdiction.is_empty()
+        && jurisdiction.len() <= MAX_JURISDICTION_LENGTH
+        && jurisdiction.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err("Jurisdiction must be a short code such as US-CA".to_string());
+    }
+    Ok(())
+}
+
+fn parse_birth_date(birth_date: &str) -> Result<time::Date, String> {
+    let format = time::format_description::parse("[year]-[month]-[day]")
+        .map_err(|_| "Invalid date format description".to_string())?;
+    time::Date::parse(birth_date, &format)
+        .map_err(|_| format!("Invalid birth date: {}", birth_date))
+}
+
+fn validate_labor_rule_set(rule_set: &LaborRuleSet) -> Result<(), String> {
+    validate_jurisdiction(&rule_set.jurisdiction)?;
+    for entry in &rule_set.rules {
+        match &entry.rule {
+            LaborRule::MaxDailyWork { minutes } | LaborRule::MaxWeeklyWork { minutes } | LaborRule::MinRest { minutes } => {
+                if *minutes == 0 {
+                    return Err("Rule durations must be positive".to_string());
+                }
+            }
+            LaborRule::MinPayRate { rate } => {
+                if !is_valid_currency(&rate.currency) {
+                    return Err("Minimum pay rate currency must be an ISO 4217 code".to_string());
+                }
+            }
+            LaborRule::MinorWorker { earliest_start_minute, latest_end_minute, .. } => {
+                if *earliest_start_minute >= 24 * 60 || *latest_end_minute > 24 * 60 {
+                    return Err("Times of day must be minutes after midnight".to_string());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Start of the shift's local calendar day, as a UTC timestamp
+fn local_day_start(shift: &Shift) -> i64 {
+    let offset_ms = clamp_utc_offset(shift.utc_offset_minutes) as i64 * 60_000;
+    let local = shift.starts_at + offset_ms;
+    local - local.rem_euclid(DAY_MS) - offset_ms
+}
+
+fn minutes_within(intervals: &[(i64, i64)], window: (i64, i64)) -> i64 {
+    intervals.iter()
+        .map(|(start, end)| (end.min(&window.1) - start.max(&window.0)).max(0))
+        .sum::<i64>() / 60_000
+}
+
+// The worker's time on the clock: completed work from their history plus
+// their committed shifts, excluding `shift` itself
+fn worker_intervals(worker_id: &UserId, shift: &Shift) -> Vec<(i64, i64)> {
+    let mut intervals: Vec<(i64, i64)> = worker_history(worker_id).iter()
+        .filter(|entry| entry.shift_id != shift.id)
+        .map(|entry| (entry.completed_at - entry.worked_minutes() as i64 * 60_000, entry.completed_at))
+        .collect();
+    intervals.extend(committed_shifts(worker_id).iter()
+        .filter(|other| other.id != shift.id)
+        .map(|other| (other.starts_at, other.ends_at)));
+    intervals
+}
+
+fn worker_age_on(worker_id: &UserId, date: time::Date) -> Option<u8> {
+    let birth_date = WORKER_PROFILES.with(|profiles| profiles.borrow().get(worker_id))?.birth_date?;
+    let birth_date = parse_birth_date(&birth_date).ok()?;
+    let mut age = date.year() - birth_date.year();
+    if (date.month() as u8, date.day()) < (birth_date.month() as u8, birth_date.day()) {
+        age -= 1;
+    }
+    u8::try_from(age.max(0)).ok()
+}
+
+// Evaluates the shift's rule set. Without a worker only the rules about the
+// shift itself apply; with one, their history and committed shifts count too.
+fn evaluate_labor_rules(shift: &Shift, worker_id: Option<&UserId>) -> Vec<ComplianceFinding> {
+    let Some(jurisdiction) = &shift.jurisdiction else {
+        return Vec::new();
+    };
+    let Some(rule_set) = LABOR_RULES.with(|rules| rules.borrow().get(jurisdiction)) else {
+        return Vec::new();
+    };
+    
+    let day_start = local_day_start(shift);
+    let day = (day_start, day_start + DAY_MS);
+    let week = (day_start - 6 * DAY_MS, day_start + DAY_MS);
+    let mut intervals = worker_id.map(|worker_id| worker_intervals(worker_id, shift)).unwrap_or_default();
+    let rest_minutes = intervals.iter()
+        .map(|(start, end)| {
+            if *end <= shift.starts_at {
+                shift.starts_at - end
+            } else if *start >= shift.ends_at {
+                start - shift.ends_at
+            } else {
+                0
+            }
+        })
+        .min()
+        .map(|gap| gap / 60_000);
+    intervals.push((shift.starts_at, shift.ends_at));
+    
+    let mut findings = Vec::new();
+    let mut finding = |rule: &str, severity: RuleSeverity, message: String| {
+        findings.push(ComplianceFinding { rule: rule.to_string(), severity, message });
+    };
+    for entry in &rule_set.rules {
+        match &entry.rule {
+            LaborRule::MinPayRate { rate } => {
+                if shift.pay_rate.currency != rate.currency {
+                    finding("MinPayRate", entry.severity, format!(
+                        "{} requires a minimum rate in {}, but the shift pays in {}",
+                        rule_set.jurisdiction, rate.currency, shift.pay_rate.currency
+                    ));
+                } else if shift.pay_rate.amount_minor < rate.amount_minor {
+                    finding("MinPayRate", entry.severity, format!(
+                        "Pay rate of {} {} per hour is below the {} minimum of {}",
+                        shift.pay_rate.amount_minor, shift.pay_rate.currency, rule_set.jurisdiction, rate.amount_minor
+                    ));
+                }
+            }
+            LaborRule::MaxDailyWork { minutes } if worker_id.is_some() => {
+                let worked = minutes_within(&intervals, day);
+                if worked > *minutes as i64 {
+                    finding("MaxDailyWork", entry.severity, format!(
+                        "Worker would work {} minutes on {}, over the {} limit of {}",
+                        worked, shift_local_date(shift), rule_set.jurisdiction, minutes
+                    ));
+                }
+            }
+            LaborRule::MaxWeeklyWork { minutes } if worker_id.is_some() => {
+                let worked = minutes_within(&intervals, week);
+                if worked > *minutes as i64 {
+                    finding("MaxWeeklyWork", entry.severity, format!(
+                        "Worker would work {} minutes in the week ending {}, over the {} limit of {}",
+                        worked, shift_local_date(shift), rule_set.jurisdiction, minutes
+                    ));
+                }
+            }
+            LaborRule::MinRest { minutes } => {
+                if let Some(rest) = rest_minutes.filter(|rest| *rest < *minutes as i64) {
+                    finding("MinRest", entry.severity, format!(
+                        "Worker would rest only {} minutes between shifts; {} requires {}",
+                        rest, rule_set.jurisdiction, minutes
+                    ));
+                }
+            }
+            LaborRule::MinorWorker { adult_age, max_daily_minutes, earliest_start_minute, latest_end_minute } => {
+                let Some(worker_id) = worker_id else { continue };
+                let local_date = OffsetDateTime::from_unix_timestamp_nanos(shift.starts_at as i128 * 1_000_000)
+                    .map(|instant| instant.to_offset(utc_offset(shift.utc_offset_minutes)).date());
+                let Some(age) = local_date.ok().and_then(|date| worker_age_on(worker_id, date)) else { continue };
+                if age >= *adult_age {
+                    continue;
+                }
+                let start_minute = (shift.starts_at - day_start) / 60_000;
+                let end_minute = (shift.ends_at - day_start) / 60_000;
+                if start_minute < *earliest_start_minute as i64 || end_minute > *latest_end_minute as i64 {
+                    finding("MinorWorker", entry.severity, format!(
+                        "Workers under {} in {} may only work between {:02}:{:02} and {:02}:{:02}",
+                        adult_age, rule_set.jurisdiction,
+                        earliest_start_minute / 60, earliest_start_minute % 60,
+                        latest_end_minute / 60, latest_end_minute % 60
+                    ));
+                }
+                let worked = minutes_within(&intervals, day);
+                if worked > *max_daily_minutes as i64 {
+                    finding("MinorWorker", entry.severity, format!(
+                        "Workers under {} in {} may work at most {} minutes a day; this would make {}",
+                        adult_age, rule_set.jurisdiction, max_daily_minutes, worked
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    findings
+}
+
+// Rejects the action if any finding blocks, otherwise returns the warnings
+fn check_labor_rules(shift: &Shift, worker_id: Option<&UserId>) -> Result<Vec<ComplianceFinding>, String> {
+    let findings = evaluate_labor_rules(shift, worker_id);
+    let blocking: Vec<&str> = findings.iter()
+        .filter(|finding| finding.severity == RuleSeverity::Block)
+        .map(|finding| finding.message.as_str())
+        .collect();
+    if !blocking.is_empty() {
+        return Err(format!("Labor rule violation: {}", blocking.join("; ")));
+    }
+    Ok(findings)
+}
+
+fn record_compliance_warnings(shift: &Shift, worker_id: Option<&UserId>, action: &str, findings: Vec<ComplianceFinding>) {
+    if findings.is_empty() {
+        return;
+    }
+    let record = ComplianceRecord {
+        id: generate_id(),
+        shift_id: shift.id.clone(),
+        worker_id: worker_id.cloned(),
+        jurisdiction: shift.jurisdiction.clone().unwrap_or_default(),
+        action: action.to_string(),
+        findings,
+        recorded_at: get_current_time(),
+    };
+    save_compliance_record(record);
+}
+
+fn save_compliance_record(record: ComplianceRecord) {
+    COMPLIANCE_RECORDS_BY_TIME.with(|index| {
+        index.borrow_mut().insert((record.shift_id.clone(), record.recorded_at.max(0) as u64, record.id.clone()), ())
+    });
+    COMPLIANCE_RECORDS.with(|records| {
+        records.borrow_mut().insert((record.shift_id.clone(), record.id.clone()), record)
+    });
+}
+
+fn compliance_records(shift_id: &ShiftId, page: &PageRequest) -> Result<Page<ComplianceRecord>, String> {
+    let (keys, next_cursor) = COMPLIANCE_RECORDS_BY_TIME.with(|index| {
+        page_index(&index.borrow(), shift_id, page, SortDirection::Asc)
+    })?;
+    let items = COMPLIANCE_RECORDS.with(|records| {
+        let records = records.borrow();
+        keys.into_iter().filter_map(|((shift_id, _, id), _)| records.get(&(shift_id, id))).collect()
+    });
+    Ok(Page { items, next_cursor, total: None })
+}
+
+// Rescheduling checks for a shift: every worker still due on it must stay
+// free of overlaps and within the labor rules. Returns the warnings to record
+// per worker, or the shift-level ones when nobody is seated.
+fn check_rescheduled_shift(shift: &Shift) -> Result<Vec<WorkerFindings>, String> {
+    let committed_workers: Vec<UserId> = if is_active_shift(shift) {
+        shift.seats.iter()
+            .filter(|seat| seat.status != SeatStatus::Completed)
+            .map(|seat| seat.worker_id.clone())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let mut warnings = Vec::new();
+    for worker_id in committed_workers {
+        if let Some(conflict) = schedule_conflict(&worker_id, shift) {
+            return Err(schedule_conflict_error(&format!("Assigned worker {} is", worker_id), &conflict));
+        }
+        let findings = check_labor_rules(shift, Some(&worker_id))?;
+        warnings.push((Some(worker_id), findings));
+    }
+    if warnings.is_empty() {
+        warnings.push((None, check_labor_rules(shift, None)?));
+    }
+    Ok(warnings)
+}
+
+// Events
+fn validate_event(event: &Event) -> Result<(), String> {
+    let name = event.name.trim();
+    if name.is_empty() || name.len() > MAX_EVENT_NAME_LENGTH {
+        return Err(format!("Event name must be 1 to {} characters", MAX_EVENT_NAME_LENGTH));
+    }
+    if event.ends_at <= event.starts_at {
+        return Err("Event must end after it starts".to_string());
+    }
+    if event.ends_at - event.starts_at > MAX_EVENT_DURATION_MS {
+        return Err("Events cannot be longer than 14 days".to_string());
+    }
+    if !is_valid_utc_offset(event.utc_offset_minutes) {
+        return Err("UTC offset must be within +/-18 hours".to_string());
+    }
+    if event.time_zone.as_deref().is_some_and(|name| !is_valid_time_zone(name)) {
+        return Err("Time zone must be an IANA name such as Europe/Berlin".to_string());
+    }
+    if let Some(point) = &event.coordinates {
+        validate_geo_point(point)?;
+    }
+    Ok(())
+}
+
+fn check_shift_in_event(shift: &Shift, event: &Event) -> Result<(), String> {
+    if shift.starts_at < event.starts_at || shift.ends_at > event.ends_at {
+        return Err(format!("Shift must fall within the event \"{}\"", event.name));
+    }
+    Ok(())
+}
+
+fn get_owned_event(event_id: &EventId, action: &str) -> Result<Event, String> {
+    let event = EVENTS.with(|events| events.borrow().get(event_id))
+        .ok_or_else(|| "Event not found".to_string())?;
+    let user_profile = get_caller_profile()?;
+    if event.business_id != user_profile.id {
+        return Err(format!("Unauthorized: Cannot {} another business's event", action));
+    }
+    Ok(event)
+}
+
+fn paginate_events<T>(items: Vec<T>, page: &PageRequest, event: impl Fn(&T) -> &Event) -> Result<Page<T>, String> {
+    let sort_by = page.sort_by.unwrap_or(SortKey::Date);
+    if sort_by == SortKey::PayRate {
+        return Err("Events cannot be sorted by pay rate".to_string());
+    }
+    paginate(items, page, SortDirection::Asc, |item| {
+        let event = event(item);
+        let value = match sort_by {
+            SortKey::CreatedAt => event.created_at,
+            _ => event.starts_at,
+        };
+        (value as i128, event.id.clone())
+    })
+}
+
+// Shifts that are going ahead or already worked
+fn is_live_event_shift(shift: &Shift) -> bool {
+    !matches!(shift.status, ShiftStatus::Cancelled | ShiftStatus::Expired)
+}
+
+fn add_money(totals: &mut Vec<Money>, amount: Money) {
+    match totals.iter_mut().find(|total| total.currency == amount.currency) {
+        Some(total) => total.amount_minor = total.amount_minor.saturating_add(amount.amount_minor),
+        None => totals.push(amount),
+    }
+}
+
+fn event_summary(event: Event) -> EventSummary {
+    let mut roles: Vec<RoleStaffing> = Vec::new();
+    let mut estimated_cost = Vec::new();
+    let mut earned_cost = Vec::new();
+    for shift in event_shifts(&event.id).into_iter().filter(is_live_event_shift) {
+        let filled = shift.seats.len() as u32;
+        let open_seats = if matches!(shift.status, ShiftStatus::Open | ShiftStatus::Claimed) {
+            shift.headcount.saturating_sub(filled)
+        } else {
+            0
+        };
+        let index = match roles.iter().position(|staffing| staffing.role == shift.role) {
+            Some(index) => index,
+            None => {
+                roles.push(RoleStaffing {
+                    role: shift.role.clone(),
+                    shift_ids: Vec::new(),
+                    headcount: 0,
+                    filled: 0,
+                    open_seats: 0,
+                });
+                roles.len() - 1
+            }
+        };
+        let staffing = &mut roles[index];
+        staffing.shift_ids.push(shift.id.clone());
+        staffing.headcount += shift.headcount;
+        staffing.filled += filled;
+        staffing.open_seats += open_seats;
+        
+        let mut estimate = shift.pay_rate.for_duration_ms(shift.ends_at - shift.starts_at);
+        estimate.amount_minor = estimate.amount_minor.saturating_mul(shift.headcount as u64);
+        add_money(&mut estimated_cost, estimate);
+        for work_history_id in shift.seats.iter().filter_map(|seat| seat.work_history_id.as_ref()) {
+            if let Some(entry) = WORK_HISTORY.with(|history| history.borrow().get(work_history_id)) {
+                add_money(&mut earned_cost, entry.pay_earned);
+            }
+        }
+    }
+    EventSummary {
+        headcount: roles.iter().map(|staffing| staffing.headcount).sum(),
+        filled: roles.iter().map(|staffing| staffing.filled).sum(),
+        event,
+        roles,
+        estimated_cost,
+        earned_cost,
+    }
+}
+
+fn worker_history(worker_id: &UserId) -> Vec<WorkHistory> {
+    let ids: Vec<WorkHistoryId> = WORK_HISTORY_BY_WORKER.with(|index| {
+        index.borrow().range((worker_id.clone(), String::new())..)
+            .take_while(|((owner, _), _)| owner == worker_id)
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    WORK_HISTORY.with(|history| {
+        let history = history.borrow();
+        ids.iter().filter_map(|id| history.get(id)).collect()
+    })
+}
+
+fn worker_ratings(worker_id: &UserId) -> Vec<Rating> {
+    let ids: Vec<RatingId> = RATINGS_BY_WORKER.with(|index| {
+        index.borrow().range((worker_id.clone(), String::new())..)
+            .take_while(|((owner, _), _)| owner == worker_id)
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    RATINGS.with(|ratings| {
+        let ratings = ratings.borrow();
+        ids.iter().filter_map(|id| ratings.get(id)).collect()
+    })
+}
+
+fn event_shifts(event_id: &EventId) -> Vec<Shift> {
+    let ids: Vec<ShiftId> = SHIFTS_BY_EVENT.with(|index| {
+        index.borrow().range((event_id.clone(), String::new())..)
+            .take_while(|((event, _), _)| event == event_id)
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    SHIFTS.with(|shifts| {
+        let shifts = shifts.borrow();
+        ids.iter().filter_map(|id| shifts.get(id)).collect()
+    })
+}
+
+fn business_events(business_id: &UserId) -> Vec<Event> {
+    let ids: Vec<EventId> = EVENTS_BY_BUSINESS.with(|index| {
+        index.borrow().range((business_id.clone(), String::new())..)
+            .take_while(|((owner, _), _)| owner == business_id)
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    EVENTS.with(|events| {
+        let events = events.borrow();
+        ids.iter().filter_map(|id| events.get(id)).collect()
+    })
+}
+
+// Rebuilds every secondary index from the primary maps. Runs on upgrade when
+// the indexes are missing and is exposed to controllers for repairs.
+fn rebuild_indexes() {
+    SHIFTS_BY_BUSINESS.with(|index| index.borrow_mut().clear_new());
+    SHIFTS_BY_STATUS.with(|index| index.borrow_mut().clear_new());
+    SHIFTS_BY_GEOHASH.with(|index| index.borrow_mut().clear_new());
+    SHIFTS_BY_WORKER.with(|index| index.borrow_mut().clear_new());
+    SHIFTS_BY_EVENT.with(|index| index.borrow_mut().clear_new());
+    EVENTS_BY_BUSINESS.with(|index| index.borrow_mut().clear_new());
+    WORK_HISTORY_BY_WORKER.with(|index| index.borrow_mut().clear_new());
+    RATINGS_BY_WORKER.with(|index| index.borrow_mut().clear_new());
+    index_worker_profiles();
+    index_user_principals();
+    index_advances();
+    index_compliance_records();
+    SCHEDULE_BY_WORKER.with(|index| index.borrow_mut().clear_new());
+    
+    let shifts: Vec<Shift> = SHIFTS.with(|shifts| shifts.borrow().iter().map(|(_, shift)| shift).collect());
+    for shift in shifts {
+        SHIFTS_BY_STATUS.with(|index| index.borrow_mut().insert(shift_status_key(&shift), ()));
+        if let Some(key) = shift_geohash_key(&shift) {
+            SHIFTS_BY_GEOHASH.with(|index| index.borrow_mut().insert(key, ()));
+        }
+        for seat in &shift.seats {
+            SHIFTS_BY_WORKER.with(|index| index.borrow_mut().insert((seat.worker_id.clone(), shift.id.clone()), ()));
+        }
+        for key in schedule_keys(&shift) {
+            SCHEDULE_BY_WORKER.with(|index| index.borrow_mut().insert(key, ()));
+        }
+        if let Some(event_id) = &shift.event_id {
+            SHIFTS_BY_EVENT.with(|index| index.borrow_mut().insert((event_id.clone(), shift.id.clone()), ()));
+        }
+        SHIFTS_BY_BUSINESS.with(|index| index.borrow_mut().insert((shift.business_id, shift.id), ()));
+    }
+    let events: Vec<Event> = EVENTS.with(|events| events.borrow().iter().map(|(_, event)| event).collect());
+    for event in events {
+        EVENTS_BY_BUSINESS.with(|index| index.borrow_mut().insert((event.business_id, event.id), ()));
+    }
+    let history: Vec<WorkHistory> = WORK_HISTORY.with(|history| history.borrow().iter().map(|(_, entry)| entry).collect());
+    for entry in history {
+        WORK_HISTORY_BY_WORKER.with(|index| index.borrow_mut().insert((entry.worker_id, entry.id), ()));
+    }
+    let ratings: Vec<Rating> = RATINGS.with(|ratings| ratings.borrow().iter().map(|(_, rating)| rating).collect());
+    for rating in ratings {
+        RATINGS_BY_WORKER.with(|index| index.borrow_mut().insert((rating.worker_id, rating.id), ()));
+    }
+}
+
+fn index_schedules() {
+    SCHEDULE_BY_WORKER.with(|index| index.borrow_mut().clear_new());
+    let shifts: Vec<Shift> = SHIFTS.with(|shifts| shifts.borrow().iter().map(|(_, shift)| shift).collect());
+    for key in shifts.iter().flat_map(schedule_keys) {
+        SCHEDULE_BY_WORKER.with(|index| index.borrow_mut().insert(key, ()));
+    }
+}
+
+fn index_compliance_records() {
+    COMPLIANCE_RECORDS_BY_TIME.with(|index| index.borrow_mut().clear_new());
+    let records: Vec<ComplianceRecord> = COMPLIANCE_RECORDS.with(|records| records.borrow().iter().map(|(_, record)| record).collect());
+    for record in records {
+        COMPLIANCE_RECORDS_BY_TIME.with(|index| {
+            index.borrow_mut().insert((record.shift_id, record.recorded_at.max(0) as u64, record.id), ())
+        });
+    }
+}
+
+fn index_user_principals() {
+    let users: Vec<(Principal, UserId)> = PRINCIPAL_TO_USERID.with(|mapping| mapping.borrow().iter().collect());
+    USERID_TO_PRINCIPAL.with(|mapping| {
+        let mut mapping = mapping.borrow_mut();
+        for (principal, user_id) in users {
+            mapping.insert(user_id, principal);
+        }
+    });
+}
+
+fn index_worker_profiles() {
+    WORKERS_BY_LOCATION.with(|index| index.borrow_mut().clear_new());
+    WORKERS_BY_GEOHASH.with(|index| index.borrow_mut().clear_new());
+    let users: Vec<UserProfile> = USER_PROFILES.with(|profiles| profiles.borrow().iter().map(|(_, profile)| profile).collect());
+    for profile in users.iter().filter(|profile| profile.user_type == UserType::Worker) {
+        WORKERS_BY_LOCATION.with(|index| {
+            index.borrow_mut().insert((location_key(&profile.location), profile.id.clone()), ())
+        });
+    }
+    let workers: Vec<WorkerProfile> = WORKER_PROFILES.with(|profiles| profiles.borrow().iter().map(|(_, profile)| profile).collect());
+    for key in workers.iter().filter_map(worker_geohash_key) {
+        WORKERS_BY_GEOHASH.with(|index| index.borrow_mut().insert(key, ()));
+    }
+}
+
+fn indexes_missing() -> bool {
+    let shifts_unindexed = SHIFTS.with(|shifts| !shifts.borrow().is_empty())
+        && SHIFTS_BY_STATUS.with(|index| index.borrow().is_empty());
+    // The worker index arrived after the others
+    let assignments_unindexed = SHIFTS_BY_WORKER.with(|index| index.borrow().is_empty())
+        && SHIFTS.with(|shifts| shifts.borrow().iter().any(|(_, shift)| !shift.seats.is_empty()));
+    let history_unindexed = WORK_HISTORY.with(|history| !history.borrow().is_empty())
+        && WORK_HISTORY_BY_WORKER.with(|index| index.borrow().is_empty());
+    let ratings_unindexed = RATINGS.with(|ratings| !ratings.borrow().is_empty())
+        && RATINGS_BY_WORKER.with(|index| index.borrow().is_empty());
+    shifts_unindexed || assignments_unindexed || history_unindexed || ratings_unindexed
+}
+
+fn rating_hundredths(average: f32) -> u32 {
+    (average as f64 * 100.0).round() as u32
+}
+
+fn calculate_average_rating(ratings: &[Rating]) -> Option<f32> {
+    if ratings.is_empty() {
+        return None;
+    }
+    let sum: u32 = ratings.iter().map(|r| r.rating as u32).sum();
+    Some(sum as f32 / ratings.len() as f32)
+}
+
+// An early check-in leaves the shift Approved, so this looks at the seats
+// rather than the status
+fn work_started(shift: &Shift) -> bool {
+    shift.seats.iter().any(|seat| seat.status != SeatStatus::Assigned)
+}
+
+fn transition_shift(shift: &mut Shift, to: ShiftStatus) -> Result<(), ShiftTransitionError> {
+    let allowed = SHIFT_TRANSITIONS.iter()
+        .any(|(from, next)| *from == shift.status && *next == to);
+    if !allowed {
+        return Err(ShiftTransitionError::InvalidTransition {
+            from: shift.status.clone(),
+            to,
+        });
+    }
+
+    if matches!(to, ShiftStatus::Approved | ShiftStatus::InProgress | ShiftStatus::Completed)
+        && shift.seats.is_empty()
+    {
+        return Err(ShiftTransitionError::WorkerNotAssigned { to });
+    }
+    if to == ShiftStatus::Cancelled && work_started(shift) {
+        return Err(ShiftTransitionError::WorkStarted);
+    }
+
+    shift.status = to;
+    shift.updated_at = get_current_time();
+    Ok(())
+}
+
+fn get_owned_shift(shift_id: &ShiftId, action: &str) -> Result<Shift, String> {
+    let caller = api::caller();
+
+    let shift = SHIFTS.with(|shifts| {
+        shifts.borrow().get(shift_id)
+            .ok_or_else(|| "Shift not found".to_string())
+    })?;
+
+    let user_profile = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })?;
+
+    if shift.business_id != user_profile.id {
+        return Err(format!("Unauthorized: Cannot {} another business's shift", action));
+    }
+
+    Ok(shift)
+}
+
+fn business_name(business_id: &UserId) -> String {
+    BUSINESS_PROFILES.with(|profiles| {
+        profiles.borrow().get(business_id)
+            .map(|profile| profile.business_name)
+            .unwrap_or_default()
+    })
+}
+
+fn get_caller_profile() -> Result<UserProfile, String> {
+    let caller = api::caller();
+    USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })
+}
+
+// Only the scheduled time plus the check-out grace is paid, so an early
+// check-in or a late check-out cannot inflate the work history
+fn billable_interval(shift: &Shift, checked_in_at: i64, checked_out_at: i64, checkout_grace_ms: i64) -> (i64, i64) {
+    let from = checked_in_at.max(shift.starts_at);
+    let to = checked_out_at.min(shift.ends_at + checkout_grace_ms).max(from);
+    (from, to)
+}
+
+// Closes out a checked-in seat and derives the work history entry from the
+// recorded timestamps instead of trusting client-supplied hours or pay. The
+// shift itself completes once no seat is left working; callers save it.
+fn complete_checked_in_seat(shift: &mut Shift, worker_id: &UserId, checked_out_at: i64) -> Result<WorkHistory, String> {
+    let seat = seat_of(shift, worker_id)
+        .ok_or_else(|| "Worker is not assigned to this shift".to_string())?;
+    let checked_in_at = seat.checked_in_at
+        .ok_or_else(|| "Worker has not checked in".to_string())?;
+    if seat.checked_out_at.is_some() {
+        return Err("Worker has already checked out".to_string());
+    }
+    if shift.status != ShiftStatus::InProgress {
+        return Err(ShiftTransitionError::InvalidTransition {
+            from: shift.status.clone(),
+            to: ShiftStatus::Completed,
+        }.into());
+    }
+    let worker_id = worker_id.clone();
+    
+    let checkout_grace_ms = read_config().checkout_grace_minutes.unwrap_or(DEFAULT_CHECKOUT_GRACE_MINUTES) as i64 * 60_000;
+    let (worked_from, worked_to) = billable_interval(shift, checked_in_at, checked_out_at, checkout_grace_ms);
+    let worked_ms = worked_to - worked_from;
+    let minutes_worked = ((worked_ms + 30_000) / 60_000) as u32;
+    let business_name = business_name(&shift.business_id);
+    
+    let mut work_history = WorkHistory {
+        id: generate_id(),
+        worker_id: worker_id.clone(),
+        business_id: shift.business_id.clone(),
+        shift_id: shift.id.clone(),
+        role: shift.role.clone(),
+        date_worked: shift_local_date(shift),
+        hours_worked: minutes_worked as f32 / 60.0,
+        minutes_worked: Some(minutes_worked),
+        pay_earned: shift.pay_rate.for_duration_ms(worked_ms),
+        business_name,
+        location: shift.location.clone(),
+        completed_at: checked_out_at,
+        verification_hash: String::new(),
+        confirmed_at: None,
+        signature: None,
+        settled_at: None,
+    };
+    work_history.verification_hash = work_history_hash(&work_history);
+    
+    save_work_history(&work_history);
+    
+    if let Some(seat) = seat_of_mut(shift, &worker_id) {
+        seat.status = SeatStatus::Completed;
+        seat.checked_out_at = Some(checked_out_at);
+        seat.work_history_id = Some(work_history.id.clone());
+    }
+    if shift.seats.iter().all(|seat| seat.status == SeatStatus::Completed) {
+        transition_shift(shift, ShiftStatus::Completed)?;
+    } else {
+        shift.updated_at = get_current_time();
+    }
+    
+    register_credential(CredentialKind::WorkHistory, &work_history.id, &worker_id, &shift.business_id);
+    
+    // Update worker's DID document and completed shift count
+    DID_DOCUMENTS.with(|docs| {
+        let existing = docs.borrow().get(&worker_id);
+        if let Some(mut did_doc) = existing {
+            did_doc.work_history.push(work_history.id.clone());
+            docs.borrow_mut().insert(worker_id.clone(), did_doc);
+        }
+    });
+    refresh_worker_aggregates(&worker_id);
+    
+    push_notification(
+        &worker_id,
+        NotificationType::ShiftCompleted,
+        "Shift completed",
+        format!("Your {} shift is complete: {:.2} hours recorded", shift.role, work_history.hours_worked),
+        Some(shift.id.clone()),
+    );
+    push_notification(
+        &shift.business_id,
+        NotificationType::ShiftCompleted,
+        "Shift completed",
+        format!("A worker finished the {} shift on {}; the work awaits your confirmation", shift.role, work_history.date_worked),
+        Some(shift.id.clone()),
+    );
+    
+    Ok(work_history)
+}
+
+fn change_shift_status(shift_id: ShiftId, to: ShiftStatus, action: &str) -> Result<Shift, String> {
+    let mut shift = get_owned_shift(&shift_id, action)?;
+    transition_shift(&mut shift, to)?;
+
+    save_shift(&shift);
+
+    Ok(shift)
+}
+
+// User Management Functions
+#[update]
+fn create_user_profile(mut profile: UserProfile) -> Result<UserProfile, String> {
+    let caller = api::caller();
+    
+    // Check if user already exists
+    if USER_PROFILES.with(|profiles| profiles.borrow().contains_key(&caller)) {
+        return Err("User profile already exists".to_string());
+    }
+    
+    let user_id = generate_id();
+    profile.id = user_id.clone();
+    profile.owner_principal = caller;
+    profile.created_at = get_current_time();
+    profile.updated_at = get_current_time();
+    
+    save_user_profile(&profile);
+    
+    PRINCIPAL_TO_USERID.with(|mapping| {
+        mapping.borrow_mut().insert(caller, user_id.clone())
+    });
+    USERID_TO_PRINCIPAL.with(|mapping| mapping.borrow_mut().insert(user_id, caller));
+    
+    Ok(profile)
+}
+
+#[query]
+fn get_user_profile(principal: Principal) -> Result<UserProfile, String> {
+    USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&principal)
+            .ok_or_else(|| "User profile not found".to_string())
+    })
+}
+
+#[update]
+fn update_user_profile(mut profile: UserProfile) -> Result<UserProfile, String> {
+    let caller = api::caller();
+    
+    // Verify caller owns this profile
+    if profile.owner_principal != caller {
+        return Err("Unauthorized: Cannot update another user's profile".to_string());
+    }
+    
+    profile.updated_at = get_current_time();
+    
+    save_user_profile(&profile);
+    
+    Ok(profile)
+}
+
+// Worker Profile Functions
+#[update]
+fn create_worker_profile(profile: WorkerProfile) -> Result<WorkerProfile, String> {
+    let caller = api::caller();
+    
+    // Verify user exists and is a worker
+    let user_profile = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })?;
+    
+    if user_profile.user_type != UserType::Worker {
+        return Err("User is not registered as a worker".to_string());
+    }
+    
+    if profile.user_id != user_profile.id {
+        return Err("User ID mismatch".to_string());
+    }
+    if let Some(point) = &profile.home_area {
+        validate_geo_point(point)?;
+    }
+    if let Some(birth_date) = &profile.birth_date {
+        parse_birth_date(birth_date)?;
+    }
+    
+    save_worker_profile(&profile);
+    
+    // Initialize empty DID document
+    let did_doc = DIDDocument {
+        worker_id: profile.user_id.clone(),
+        work_history: Vec::new(),
+        ratings: Vec::new(),
+        total_shifts: 0,
+        average_rating: None,
+        skills_verified: profile.skills.clone(),
+        created_at: get_current_time(),
+        updated_at: get_current_time(),
+        signature: String::new(),
+        signed_digest: None,
+    };
+    
+    DID_DOCUMENTS.with(|docs| {
+        docs.borrow_mut().insert(profile.user_id.clone(), did_doc)
+    });
+    
+    Ok(profile)
+}
+
+#[query]
+fn get_worker_profile(user_id: UserId) -> Result<WorkerProfile, String> {
+    WORKER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&user_id)
+            .ok_or_else(|| "Worker profile not found".to_string())
+    })
+}
+
+#[update]
+fn update_worker_profile(profile: WorkerProfile) -> Result<WorkerProfile, String> {
+    let caller = api::caller();
+    
+    // Verify caller owns this profile
+    let user_profile = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })?;
+    
+    if profile.user_id != user_profile.id {
+        return Err("Unauthorized: Cannot update another user's profile".to_string());
+    }
+    if let Some(point) = &profile.home_area {
+        validate_geo_point(point)?;
+    }
+    if let Some(birth_date) = &profile.birth_date {
+        parse_birth_date(birth_date)?;
+    }
+    
+    save_worker_profile(&profile);
+    
+    Ok(profile)
+}
+
+// Business Profile Functions
+#[update]
+fn create_business_profile(profile: BusinessProfile) -> Result<BusinessProfile, String> {
+    let caller = api::caller();
+    
+    // Verify user exists and is a business
+    let user_profile = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })?;
+    
+    if user_profile.user_type != UserType::Business {
+        return Err("User is not registered as a business".to_string());
+    }
+    
+    if profile.user_id != user_profile.id {
+        return Err("User ID mismatch".to_string());
+    }
+    if let Some(point) = &profile.coordinates {
+        validate_geo_point(point)?;
+    }
+    
+    BUSINESS_PROFILES.with(|profiles| {
+        profiles.borrow_mut().insert(profile.user_id.clone(), profile.clone())
+    });
+    
+    Ok(profile)
+}
+
+#[query]
+fn get_business_profile(user_id: UserId) -> Result<BusinessProfile, String> {
+    BUSINESS_PROFILES.with(|profiles| {
+        profiles.borrow().get(&user_id)
+            .ok_or_else(|| "Business profile not found".to_string())
+    })
+}
+
+#[update]
+fn update_business_profile(profile: BusinessProfile) -> Result<BusinessProfile, String> {
+    let caller = api::caller();
+    
+    // Verify caller owns this profile
+    let user_profile = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })?;
+    
+    if profile.user_id != user_profile.id {
+        return Err("Unauthorized: Cannot update another user's profile".to_string());
+    }
+    if let Some(point) = &profile.coordinates {
+        validate_geo_point(point)?;
+    }
+    
+    BUSINESS_PROFILES.with(|profiles| {
+        profiles.borrow_mut().insert(profile.user_id.clone(), profile.clone())
+    });
+    
+    Ok(profile)
+}
+
+// Shift Management Functions
+#[update]
+fn create_shift(mut shift: Shift) -> Result<Shift, String> {
+    let caller = api::caller();
+    
+    // Verify caller is a business
+    let user_profile = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })?;
+    
+    if user_profile.user_type != UserType::Business {
+        return Err("Only businesses can create shifts".to_string());
+    }
+    
+    if shift.business_id != user_profile.id {
+        return Err("Business ID mismatch".to_string());
+    }
+    
+    if !is_valid_currency(&shift.pay_rate.currency) {
+        return Err("Pay rate currency must be an ISO 4217 code".to_string());
+    }
+    validate_shift_schedule(&shift)?;
+    validate_headcount(shift.headcount)?;
+    if let Some(point) = &shift.coordinates {
+        validate_geo_point(point)?;
+    }
+    shift.jurisdiction = shift.jurisdiction.as_deref().map(normalize_jurisdiction);
+    if let Some(jurisdiction) = &shift.jurisdiction {
+        validate_jurisdiction(jurisdiction)?;
+    }
+    
+    // New shifts start as a draft or go straight to open; later moves use the transition endpoints
+    if shift.status != ShiftStatus::Draft && shift.status != ShiftStatus::Open {
+        return Err("New shifts must be created as Draft or Open".to_string());
+    }
+    
+    // Shifts created under an event share its details unless they set their own
+    if let Some(event_id) = &shift.event_id {
+        let event = EVENTS.with(|events| events.borrow().get(event_id))
+            .ok_or_else(|| "Event not found".to_string())?;
+        if event.business_id != user_profile.id {
+            return Err("Unauthorized: Cannot add shifts to another business's event".to_string());
+        }
+        if event.status != EventStatus::Active {
+            return Err("Cannot add shifts to a cancelled event".to_string());
+        }
+        check_shift_in_event(&shift, &event)?;
+        if shift.location.trim().is_empty() {
+            shift.location = event.location;
+        }
+        if shift.description.is_none() {
+            shift.description = event.description;
+        }
+        if shift.coordinates.is_none() {
+            shift.coordinates = event.coordinates;
+        }
+    }
+    
+    // Shifts without coordinates are placed at the business address
+    if shift.coordinates.is_none() {
+        shift.coordinates = BUSINESS_PROFILES.with(|profiles| profiles.borrow().get(&user_profile.id))
+            .and_then(|business| business.coordinates);
+    }
+    
+    let warnings = check_labor_rules(&shift, None)?;
+    
+    let shift_id = generate_id();
+    shift.id = shift_id.clone();
+    shift.created_at = get_current_time();
+    shift.updated_at = get_current_time();
+    shift.seats = Vec::new();
+    shift.applicants = Vec::new();
+    
+    save_shift(&shift);
+    record_compliance_warnings(&shift, None, "create_shift", warnings);
+    
+    if shift.status == ShiftStatus::Open {
+        notify_shift_posted(&shift);
+    }
+    
+    Ok(shift)
+}
+
+#[query]
+fn get_shift(shift_id: ShiftId) -> Result<Shift, String> {
+    SHIFTS.with(|shifts| {
+        shifts.borrow().get(&shift_id)
+            .ok_or_else(|| "Shift not found".to_string())
+    })
+}
+
+#[update]
+fn update_shift(shift_id: ShiftId, mut shift: Shift) -> Result<Shift, String> {
+    let caller = api::caller();
+    
+    // Get existing shift
+    let existing_shift = SHIFTS.with(|shifts| {
+        shifts.borrow().get(&shift_id)
+            .ok_or_else(|| "Shift not found".to_string())
+    })?;
+    
+    // Verify caller owns this shift
+    let user_profile = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })?;
+    
+    if existing_shift.business_id != user_profile.id {
+        return Err("Unauthorized: Cannot update another business's shift".to_string());
+    }
+    
+    if !is_valid_currency(&shift.pay_rate.currency) {
+        return Err("Pay rate currency must be an ISO 4217 code".to_string());
+    }
+    validate_shift_schedule(&shift)?;
+    validate_headcount(shift.headcount)?;
+    if let Some(point) = &shift.coordinates {
+        validate_geo_point(point)?;
+    }
+    shift.jurisdiction = shift.jurisdiction.as_deref().map(normalize_jurisdiction);
+    if let Some(jurisdiction) = &shift.jurisdiction {
+        validate_jurisdiction(jurisdiction)?;
+    }
+    if (shift.headcount as usize) < existing_shift.seats.len() {
+        return Err(format!(
+            "Headcount cannot drop below the {} workers already assigned; release seats first",
+            existing_shift.seats.len()
+        ));
+    }
+    
+    // Lifecycle fields are owned by the transition endpoints and cannot be overwritten here
+    shift.id = shift_id.clone();
+    shift.business_id = existing_shift.business_id;
+    shift.event_id = existing_shift.event_id;
+    shift.status = existing_shift.status;
+    shift.seats = existing_shift.seats;
+    shift.applicants = existing_shift.applicants;
+    shift.created_at = existing_shift.created_at;
+    shift.updated_at = get_current_time();
+    
+    // A headcount change can fill or open up seats
+    let staffing_change = match shift.status {
+        ShiftStatus::Open | ShiftStatus::Claimed if !shift.seats.is_empty() && is_fully_staffed(&shift) => {
+            Some(ShiftStatus::Approved)
+        }
+        ShiftStatus::Approved if !is_fully_staffed(&shift) => Some(ShiftStatus::Open),
+        _ => None,
+    };
+    if let Some(to) = staffing_change {
+        transition_shift(&mut shift, to)?;
+    }
+    
+    if let Some(event) = shift.event_id.as_ref().and_then(|id| EVENTS.with(|events| events.borrow().get(id))) {
+        check_shift_in_event(&shift, &event)?;
+    }
+    
+    // Rescheduling must not double-book a seated worker or break the labor rules
+    let warnings = check_rescheduled_shift(&shift)?;
+    check_escrow_covers(&shift)?;
+    
+    save_shift(&shift);
+    for (worker_id, findings) in warnings {
+        record_compliance_warnings(&shift, worker_id.as_ref(), "update_shift", findings);
+    }
+    
+    Ok(shift)
+}
+
+// Shift Lifecycle Functions
+#[update]
+fn publish_shift(shift_id: ShiftId) -> Result<Shift, String> {
+    let shift = change_shift_status(shift_id, ShiftStatus::Open, "publish")?;
+    notify_shift_posted(&shift);
+    Ok(shift)
+}
+
+#[update]
+fn start_shift(shift_id: ShiftId) -> Result<Shift, String> {
+    let shift = change_shift_status(shift_id, ShiftStatus::InProgress, "start")?;
+    for worker_id in seated_workers(&shift) {
+        push_notification(
+            &worker_id,
+            NotificationType::ShiftStarted,
+            "Shift started",
+            format!("The business has started your {} shift; remember to check in", shift.role),
+            Some(shift.id.clone()),
+        );
+    }
+    Ok(shift)
+}
+
+#[update]
+async fn complete_shift(shift_id: ShiftId) -> Result<Shift, String> {
+    // Business-side completion checks every working seat out on the workers'
+    // behalf; seats that never checked in are closed as no-shows and refunded
+    let mut shift = get_owned_shift(&shift_id, "complete")?;
+    let checked_in: Vec<UserId> = shift.seats.iter()
+        .filter(|seat| seat.status == SeatStatus::CheckedIn)
+        .map(|seat| seat.worker_id.clone())
+        .collect();
+    if shift.seats.iter().all(|seat| seat.status == SeatStatus::Assigned) {
+        return Err("No worker has checked in; cancel the shift to refund the no-shows".to_string());
+    }
+    let now = get_current_time();
+    for worker_id in &checked_in {
+        complete_checked_in_seat(&mut shift, worker_id, now)?;
+    }
+    if shift.status != ShiftStatus::Completed {
+        transition_shift(&mut shift, ShiftStatus::Completed)?;
+    }
+    save_shift(&shift);
+    settle_shift_escrows_after_change(&shift_id).await;
+    Ok(shift)
+}
+
+#[update]
+async fn cancel_shift(shift_id: ShiftId) -> Result<Shift, String> {
+    let shift = change_shift_status(shift_id.clone(), ShiftStatus::Cancelled, "cancel")?;
+    notify_shift_cancelled(&shift, &shift_workers(&shift));
+    settle_shift_escrows_after_change(&shift_id).await;
+    Ok(shift)
+}
+
+#[update]
+async fn reopen_shift(shift_id: ShiftId) -> Result<Shift, String> {
+    let mut shift = get_owned_shift(&shift_id, "reopen")?;
+    let previous_workers = release_all_seats(&mut shift)?;
+    save_shift(&shift);
+    for worker_id in previous_workers {
+        push_notification(
+            &worker_id,
+            NotificationType::ShiftCancelled,
+            "Assignment withdrawn",
+            format!("The business reopened the {} shift on {} and released your assignment", shift.role, shift_local_date(&shift)),
+            Some(shift_id.clone()),
+        );
+    }
+    settle_shift_escrows_after_change(&shift_id).await;
+    Ok(shift)
+}
+
+// Reopening releases every seated worker back to the pool, which is only
+// possible before anyone has checked in
+fn release_all_seats(shift: &mut Shift) -> Result<Vec<UserId>, String> {
+    if work_started(shift) {
+        return Err("Cannot reopen a shift once a worker has checked in".to_string());
+    }
+    transition_shift(shift, ShiftStatus::Open)?;
+    let previous_workers = seated_workers(shift);
+    shift.seats.clear();
+    Ok(previous_workers)
+}
+
+// Frees one seat before the worker has checked in, refunding its escrow; a
+// fully staffed shift goes back to Open so the seat can be filled again
+#[update]
+async fn release_seat(shift_id: ShiftId, worker_id: UserId) -> Result<Shift, String> {
+    let mut shift = get_owned_shift(&shift_id, "release seats on")?;
+    let seat = seat_of(&shift, &worker_id)
+        .ok_or_else(|| "Worker is not assigned to this shift".to_string())?;
+    if seat.status != SeatStatus::Assigned {
+        return Err("Only seats that have not checked in can be released".to_string());
+    }
+    if !matches!(shift.status, ShiftStatus::Open | ShiftStatus::Claimed | ShiftStatus::Approved) {
+        return Err(format!("Cannot release seats on a {:?} shift", shift.status));
+    }
+    
+    shift.seats.retain(|seat| seat.worker_id != worker_id);
+    if shift.status == ShiftStatus::Approved {
+        transition_shift(&mut shift, ShiftStatus::Open)?;
+    } else {
+        shift.updated_at = get_current_time();
+    }
+    save_shift(&shift);
+    
+    push_notification(
+        &worker_id,
+        NotificationType::ShiftCancelled,
+        "Assignment withdrawn",
+        format!("The business released your seat on the {} shift on {}", shift.role, shift_local_date(&shift)),
+        Some(shift_id.clone()),
+    );
+    settle_escrow_after_change(&shift_id, &worker_id).await;
+    Ok(shift)
+}
+
+#[update]
+fn delete_shift(shift_id: ShiftId) -> Result<bool, String> {
+    let caller = api::caller();
+    
+    // Get existing shift
+    let existing_shift = SHIFTS.with(|shifts| {
+        shifts.borrow().get(&shift_id)
+            .ok_or_else(|| "Shift not found".to_string())
+    })?;
+    
+    // Verify caller owns this shift
+    let user_profile = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })?;
+    
+    if existing_shift.business_id != user_profile.id {
+        return Err("Unauthorized: Cannot delete another business's shift".to_string());
+    }
+    
+    // Settlement reads the shift, so escrowed funds must be paid out or
+    // refunded before it can go
+    let unsettled = shift_escrows(&shift_id).iter()
+        .any(|escrow| matches!(escrow.status, EscrowStatus::Funding | EscrowStatus::Funded | EscrowStatus::Settling));
+    if unsettled {
+        return Err("Shift has unsettled escrow; cancel it and settle the escrow before deleting".to_string());
+    }
+    
+    // Finished shifts have nobody left to tell
+    if !matches!(existing_shift.status, ShiftStatus::Completed | ShiftStatus::Cancelled | ShiftStatus::Expired) {
+        notify_shift_cancelled(&existing_shift, &shift_workers(&existing_shift));
+    }
+    
+    remove_shift(&existing_shift);
+    
+    Ok(true)
+}
+
+#[query]
+fn get_shifts_by_business(business_id: UserId, page: PageRequest) -> Result<Page<Shift>, String> {
+    paginate_shifts(business_shifts(&business_id), &page)
+}
+
+#[query]
+fn get_available_shifts(location_filter: Option<String>, page: PageRequest) -> Result<Page<Shift>, String> {
+    let mut shifts = shifts_with_status(&ShiftStatus::Open);
+    if let Some(location) = location_filter.as_deref().map(match_key) {
+        shifts.retain(|shift| match_key(&shift.location).contains(&location));
+    }
+    
+    paginate_shifts(shifts, &page)
+}
+
+// Active shifts where the caller holds an unfinished seat, soonest first
+#[query]
+fn get_committed_schedule(page: PageRequest) -> Result<Page<Shift>, String> {
+    let user_profile = get_caller_profile()?;
+    committed_schedule(&user_profile.id, &page)
+}
+
+// Role and location text is compared trimmed and case-folded on both sides
+fn match_key(text: &str) -> String {
+    text.trim().to_lowercase()
+}
+
+// Builds a predicate for every criterion that is set. The start-time window
+// is included so callers not reading the status index still honour it.
+fn shift_search_filter(criteria: &ShiftSearchCriteria) -> Result<impl Fn(&Shift) -> bool, String> {
+    if let Some(min_pay) = &criteria.min_pay {
+        if !is_valid_currency(&min_pay.currency) {
+            return Err("Currency must be a three-letter ISO 4217 code".to_string());
+        }
+    }
+    if let (Some(after), Some(before)) = (criteria.starts_after, criteria.starts_before) {
+        if after > before {
+            return Err("starts_after must not be later than starts_before".to_string());
+        }
+    }
+    
+    let skills: Option<Vec<String>> = if criteria.match_skills.unwrap_or(false) {
+        let user_profile = get_caller_profile()?;
+        let worker_profile = WORKER_PROFILES.with(|profiles| {
+            profiles.borrow().get(&user_profile.id)
+                .ok_or_else(|| "Worker profile not found".to_string())
+        })?;
+        Some(worker_profile.skills.iter().map(|skill| skill.trim().to_lowercase()).collect())
+    } else {
+        None
+    };
+    let location = criteria.location.as_deref().map(match_key);
+    let role = criteria.role.as_deref().map(match_key);
+    let min_pay = criteria.min_pay.clone();
+    let (starts_after, starts_before) = (criteria.starts_after, criteria.starts_before);
+    let urgent_only = criteria.urgent_only.unwrap_or(false);
+    
+    Ok(move |shift: &Shift| {
+        if starts_after.is_some_and(|after| shift.starts_at < after)
+            || starts_before.is_some_and(|before| shift.starts_at > before)
+        {
+            return false;
+        }
+        if let Some(location) = &location {
+            if !match_key(&shift.location).contains(location) {
+                return false;
+            }
+        }
+        if let Some(min_pay) = &min_pay {
+            if shift.pay_rate.currency != min_pay.currency || shift.pay_rate.amount_minor < min_pay.amount_minor {
+                return false;
+            }
+        }
+        if let Some(role) = &role {
+            if match_key(&shift.role) != *role {
+                return false;
+            }
+        }
+        if urgent_only && !shift.is_urgent {
+            return false;
+        }
+        // Shifts without requirements are open to everyone
+        if let Some(skills) = &skills {
+            if !shift.requirements.is_empty()
+                && !shift.requirements.iter().any(|requirement| skills.contains(&requirement.trim().to_lowercase()))
+            {
+                return false;
+            }
+        }
+        true
+    })
+}
+
+// Open shifts matching every criterion that is set. The date window is read
+// from the status index; the remaining filters apply to that slice.
+#[query]
+fn search_shifts(criteria: ShiftSearchCriteria, page: PageRequest) -> Result<Page<Shift>, String> {
+    let matches = shift_search_filter(&criteria)?;
+    let mut shifts = shifts_with_status_between(&ShiftStatus::Open, criteria.starts_after, criteria.starts_before);
+    shifts.retain(|shift| matches(shift));
+    
+    paginate_shifts(shifts, &page)
+}
+
+// Open shifts within radius_km of a point, nearest first unless the page asks
+// for another sort key. Candidates come from the geohash index, then the exact
+// distance and the remaining criteria are checked.
+#[query]
+fn search_shifts_near(
+    latitude: f64,
+    longitude: f64,
+    radius_km: f64,
+    criteria: ShiftSearchCriteria,
+    page: PageRequest,
+) -> Result<Page<NearbyShift>, String> {
+    let center = GeoPoint { latitude, longitude };
+    validate_geo_point(&center)?;
+    if !radius_km.is_finite() || radius_km <= 0.0 || radius_km > MAX_SEARCH_RADIUS_KM {
+        return Err(format!("Radius must be greater than 0 and at most {} km", MAX_SEARCH_RADIUS_KM));
+    }
+    let matches = shift_search_filter(&criteria)?;
+    
+    let nearby: Vec<NearbyShift> = shifts_in_cells(&geohash_cells(&center, radius_km))
+        .into_iter()
+        .filter(|shift| matches(shift))
+        .filter_map(|shift| {
+            let distance_km = distance_km(&center, shift.coordinates.as_ref()?);
+            (distance_km <= radius_km).then_some(NearbyShift { shift, distance_km })
+        })
+        .collect();
+    
+    let sort_by = page.sort_by;
+    paginate(nearby, &page, SortDirection::Asc, |nearby| {
+        let value = match sort_by {
+            Some(sort_by) => shift_sort_value(&nearby.shift, sort_by),
+            None => (nearby.distance_km * 1000.0).round() as i128, // metres
+        };
+        (value, nearby.shift.id.clone())
+    })
+}
+
+// Labor Rule Functions
+#[query]
+fn get_labor_rules(jurisdiction: String) -> Result<LaborRuleSet, String> {
+    LABOR_RULES.with(|rules| {
+        rules.borrow().get(&normalize_jurisdiction(&jurisdiction))
+            .ok_or_else(|| "No labor rules for this jurisdiction".to_string())
+    })
+}
+
+// Replaces the jurisdiction's rule set; applies to checks made from now on
+#[update]
+fn set_labor_rules(mut rule_set: LaborRuleSet) -> Result<LaborRuleSet, String> {
+    if !api::is_controller(&api::caller()) {
+        return Err("Unauthorized: Only canister controllers can change labor rules".to_string());
+    }
+    rule_set.jurisdiction = normalize_jurisdiction(&rule_set.jurisdiction);
+    validate_labor_rule_set(&rule_set)?;
+    rule_set.updated_at = get_current_time();
+    
+    LABOR_RULES.with(|rules| {
+        rules.borrow_mut().insert(rule_set.jurisdiction.clone(), rule_set.clone())
+    });
+    Ok(rule_set)
+}
+
+#[update]
+fn delete_labor_rules(jurisdiction: String) -> Result<bool, String> {
+    if !api::is_controller(&api::caller()) {
+        return Err("Unauthorized: Only canister controllers can change labor rules".to_string());
+    }
+    LABOR_RULES.with(|rules| rules.borrow_mut().remove(&normalize_jurisdiction(&jurisdiction)))
+        .map(|_| true)
+        .ok_or_else(|| "No labor rules for this jurisdiction".to_string())
+}
+
+// Warnings recorded against a shift, oldest first. Visible to the business
+// that owns the shift and to controllers.
+#[query]
+fn get_compliance_records(shift_id: ShiftId, page: PageRequest) -> Result<Page<ComplianceRecord>, String> {
+    let shift = SHIFTS.with(|shifts| shifts.borrow().get(&shift_id))
+        .ok_or_else(|| "Shift not found".to_string())?;
+    let caller = api::caller();
+    let is_owner = USER_PROFILES.with(|profiles| profiles.borrow().get(&caller))
+        .is_some_and(|profile| profile.id == shift.business_id);
+    if !is_owner && !api::is_controller(&caller) {
+        return Err("Unauthorized: Only the business can view compliance records for this shift".to_string());
+    }
+    
+    compliance_records(&shift_id, &page)
+}
+
+// Event Functions
+#[update]
+fn create_event(mut event: Event) -> Result<Event, String> {
+    let user_profile = get_caller_profile()?;
+    if user_profile.user_type != UserType::Business {
+        return Err("Only businesses can create events".to_string());
+    }
+    if event.business_id != user_profile.id {
+        return Err("Business ID mismatch".to_string());
+    }
+    validate_event(&event)?;
+    
+    if event.coordinates.is_none() {
+        event.coordinates = BUSINESS_PROFILES.with(|profiles| profiles.borrow().get(&user_profile.id))
+            .and_then(|business| business.coordinates);
+    }
+    event.id = generate_id();
+    event.status = EventStatus::Active;
+    event.created_at = get_current_time();
+    event.updated_at = get_current_time();
+    
+    save_event(&event);
+    
+    Ok(event)
+}
+
+#[query]
+fn get_event(event_id: EventId) -> Result<Event, String> {
+    EVENTS.with(|events| {
+        events.borrow().get(&event_id)
+            .ok_or_else(|| "Event not found".to_string())
+    })
+}
+
+// Updates the shared details. Existing shifts keep their own location and
+// description, but the new window must still contain every live shift.
+#[update]
+fn update_event(event_id: EventId, mut event: Event) -> Result<Event, String> {
+    let existing_event = get_owned_event(&event_id, "update")?;
+    if existing_event.status != EventStatus::Active {
+        return Err("Cancelled events cannot be updated".to_string());
+    }
+    validate_event(&event)?;
+    
+    event.id = event_id.clone();
+    event.business_id = existing_event.business_id;
+    event.status = existing_event.status;
+    event.created_at = existing_event.created_at;
+    event.updated_at = get_current_time();
+    
+    for shift in event_shifts(&event_id).iter().filter(|shift| is_live_event_shift(shift)) {
+        check_shift_in_event(shift, &event)?;
+    }
+    
+    save_event(&event);
+    
+    Ok(event)
+}
+
+#[query]
+fn get_events_by_business(business_id: UserId, page: PageRequest) -> Result<Page<Event>, String> {
+    paginate_events(business_events(&business_id), &page, |event| event)
+}
+
+#[query]
+fn get_event_summary(event_id: EventId) -> Result<EventSummary, String> {
+    Ok(event_summary(get_event(event_id)?))
+}
+
+// Shifts of an event; open_only narrows them to those taking applications
+#[query]
+fn get_event_shifts(event_id: EventId, open_only: bool, page: PageRequest) -> Result<Page<Shift>, String> {
+    get_event(event_id.clone())?;
+    let mut shifts = event_shifts(&event_id);
+    if open_only {
+        shifts.retain(|shift| shift.status == ShiftStatus::Open);
+    }
+    paginate_shifts(shifts, &page)
+}
+
+// Active events with at least one open shift, each with its roles and open seats
+#[query]
+fn get_open_events(location_filter: Option<String>, page: PageRequest) -> Result<Page<EventSummary>, String> {
+    let mut event_ids: Vec<EventId> = shifts_with_status(&ShiftStatus::Open).into_iter()
+        .filter_map(|shift| shift.event_id)
+        .collect();
+    event_ids.sort();
+    event_ids.dedup();
+    
+    let location = location_filter.map(|location| location.trim().to_lowercase());
+    let summaries: Vec<EventSummary> = event_ids.iter()
+        .filter_map(|id| EVENTS.with(|events| events.borrow().get(id)))
+        .filter(|event| event.status == EventStatus::Active)
+        .filter(|event| location.as_ref().is_none_or(|location| event.location.to_lowercase().contains(location)))
+        .map(event_summary)
+        .collect();
+    
+    paginate_events(summaries, &page, |summary| &summary.event)
+}
+
+// Cancels every shift of the event that has not started, notifying seated
+// workers and applicants and refunding escrows. Shifts already in progress
+// are reported back and left running.
+#[update]
+async fn cancel_event(event_id: EventId) -> Result<EventBulkResult, String> {
+    let mut event = get_owned_event(&event_id, "cancel")?;
+    if event.status == EventStatus::Cancelled {
+        return Err("Event is already cancelled".to_string());
+    }
+    
+    let mut updated = Vec::new();
+    let mut skipped = Vec::new();
+    for mut shift in event_shifts(&event_id) {
+        if matches!(shift.status, ShiftStatus::Completed | ShiftStatus::Cancelled | ShiftStatus::Expired) {
+            continue;
+        }
+        match transition_shift(&mut shift, ShiftStatus::Cancelled) {
+            Ok(()) => {
+                save_shift(&shift);
+                notify_shift_cancelled(&shift, &shift_workers(&shift));
+                updated.push(shift.id);
+            }
+            Err(error) => skipped.push(EventShiftError { shift_id: shift.id, error: error.into() }),
+        }
+    }
+    
+    event.status = EventStatus::Cancelled;
+    event.updated_at = get_current_time();
+    save_event(&event);
+    
+    for shift_id in &updated {
+        settle_shift_escrows_after_change(shift_id).await;
+    }
+    
+    Ok(EventBulkResult { event, updated, skipped })
+}
+
+// Pushes back the end of the event and of every shift not yet finished.
+// Shifts that would break the 24-hour limit, a worker's schedule, the labor
+// rules or their seats' escrow keep their times and are reported back.
+#[update]
+fn extend_event(event_id: EventId, extra_minutes: u32) -> Result<EventBulkResult, String> {
+    if extra_minutes == 0 || extra_minutes > 24 * 60 {
+        return Err("Extension must be between 1 minute and 24 hours".to_string());
+    }
+    let mut event = get_owned_event(&event_id, "extend")?;
+    if event.status != EventStatus::Active {
+        return Err("Cancelled events cannot be extended".to_string());
+    }
+    let extra_ms = extra_minutes as i64 * 60_000;
+    event.ends_at += extra_ms;
+    event.updated_at = get_current_time();
+    validate_event(&event)?;
+    
+    let mut updated = Vec::new();
+    let mut skipped = Vec::new();
+    for mut shift in event_shifts(&event_id) {
+        if !matches!(shift.status, ShiftStatus::Draft) && !is_active_shift(&shift) {
+            continue;
+        }
+        shift.ends_at += extra_ms;
+        shift.updated_at = get_current_time();
+        let checks = validate_shift_schedule(&shift)
+            .and_then(|_| check_escrow_covers(&shift))
+            .and_then(|_| check_rescheduled_shift(&shift));
+        let warnings = match checks {
+            Ok(warnings) => warnings,
+            Err(error) => {
+                skipped.push(EventShiftError { shift_id: shift.id, error });
+                continue;
+            }
+        };
+        save_shift(&shift);
+        for (worker_id, findings) in warnings {
+            record_compliance_warnings(&shift, worker_id.as_ref(), "extend_event", findings);
+        }
+        updated.push(shift.id);
+    }
+    
+    save_event(&event);
+    
+    Ok(EventBulkResult { event, updated, skipped })
+}
+
+// Shift Application Functions
+#[update]
+fn apply_to_shift(shift_id: ShiftId, message: Option<String>) -> Result<bool, String> {
+    let caller = api::caller();
+    
+    // Verify caller is a worker
+    let user_profile = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })?;
+    
+    if user_profile.user_type != UserType::Worker {
+        return Err("Only workers can apply to shifts".to_string());
+    }
+    
+    // Get shift and verify it's open
+    let mut shift = SHIFTS.with(|shifts| {
+        shifts.borrow().get(&shift_id)
+            .ok_or_else(|| "Shift not found".to_string())
+    })?;
+    
+    if shift.status != ShiftStatus::Open {
+        return Err("Shift is not open for applications".to_string());
+    }
+    
+    // Check if worker already applied
+    if shift.applicants.contains(&user_profile.id) {
+        return Err("Already applied to this shift".to_string());
+    }
+    
+    if let Some(conflict) = schedule_conflict(&user_profile.id, &shift) {
+        return Err(schedule_conflict_error("You are", &conflict));
+    }
+    
+    // Add application
+    let application = ShiftApplication {
+        shift_id: shift_id.clone(),
+        worker_id: user_profile.id.clone(),
+        applied_at: get_current_time(),
+        message,
+        status: ApplicationStatus::Pending,
+    };
+    
+    let app_key = format!("{}_{}", shift_id, user_profile.id);
+    APPLICATIONS.with(|apps| {
+        apps.borrow_mut().insert(app_key, application)
+    });
+    
+    // Update shift with new applicant
+    shift.applicants.push(user_profile.id);
+    shift.updated_at = get_current_time();
+    
+    push_notification(
+        &shift.business_id,
+        NotificationType::ShiftClaimed,
+        "New applicant",
+        format!("{} applied to your {} shift on {}", user_profile.name, shift.role, shift_local_date(&shift)),
+        Some(shift_id.clone()),
+    );
+    
+    save_shift(&shift);
+    
+    Ok(true)
+}
+
+#[query]
+fn get_shift_applications(shift_id: ShiftId, page: PageRequest) -> Result<Page<ShiftApplication>, String> {
+    // Applications are keyed "<shift_id>_<worker_id>"
+    if page.sort_by == Some(SortKey::PayRate) {
+        return Err("Applications cannot be sorted by pay rate".to_string());
+    }
+    
+    let prefix = format!("{}_", shift_id);
+    let applications = APPLICATIONS.with(|apps| {
+        apps.borrow().range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, app)| app)
+            .collect()
+    });
+    
+    paginate(applications, &page, SortDirection::Asc, |app: &ShiftApplication| {
+        (app.applied_at as i128, app.worker_id.clone())
+    })
+}
+
+// Approvals fill seats on shifts still taking applications
+fn check_seat_available(shift: &Shift, worker_id: &UserId) -> Result<(), String> {
+    if !matches!(shift.status, ShiftStatus::Open | ShiftStatus::Claimed) {
+        return Err(format!("Cannot approve applications for a {:?} shift", shift.status));
+    }
+    if seat_of(shift, worker_id).is_some() {
+        return Err("Worker already holds a seat on this shift".to_string());
+    }
+    if is_fully_staffed(shift) {
+        return Err("Shift is already fully staffed".to_string());
+    }
+    Ok(())
+}
+
+// Rejected applications stay rejected, and approved ones already hold a seat
+fn approvable_application(app_key: &str) -> Result<ShiftApplication, String> {
+    let application = APPLICATIONS.with(|apps| apps.borrow().get(&app_key.to_string()))
+        .ok_or_else(|| "Application not found".to_string())?;
+    if application.status != ApplicationStatus::Pending {
+        return Err("Only pending applications can be approved".to_string());
+    }
+    Ok(application)
+}
+
+#[update]
+async fn approve_application(shift_id: ShiftId, worker_id: UserId) -> Result<bool, String> {
+    // Verify caller owns the shift
+    let shift = get_owned_shift(&shift_id, "approve applications for")?;
+    
+    let app_key = format!("{}_{}", shift_id, worker_id);
+    approvable_application(&app_key)?;
+    
+    // Validate the seat and the worker's schedule before any funds move
+    check_seat_available(&shift, &worker_id)?;
+    if let Some(conflict) = schedule_conflict(&worker_id, &shift) {
+        return Err(schedule_conflict_error("Worker is", &conflict));
+    }
+    check_labor_rules(&shift, Some(&worker_id))?;
+    
+    if let Some(ledger) = read_config().ledger_canister_id {
+        let estimated_pay = shift.pay_rate.for_duration_ms(shift.ends_at - shift.starts_at);
+        fund_shift_escrow(ledger, &shift, &worker_id, to_ledger_units(&estimated_pay)).await?;
+    }
+    
+    // Re-read state that may have changed while awaiting the ledger
+    let mut shift = SHIFTS.with(|shifts| shifts.borrow().get(&shift_id))
+        .ok_or_else(|| "Shift not found".to_string())?;
+    // The application may have been rejected while awaiting the ledger
+    let mut application = match approvable_application(&app_key) {
+        Ok(application) => application,
+        Err(error) => {
+            settle_escrow_after_change(&shift_id, &worker_id).await;
+            return Err(error);
+        }
+    };
+    
+    // Another approval may have taken the last seat or booked the worker in the meantime
+    if let Err(error) = check_seat_available(&shift, &worker_id) {
+        settle_escrow_after_change(&shift_id, &worker_id).await;
+        return Err(error);
+    }
+    if let Some(conflict) = schedule_conflict(&worker_id, &shift) {
+        settle_escrow_after_change(&shift_id, &worker_id).await;
+        return Err(schedule_conflict_error("Worker is", &conflict));
+    }
+    let warnings = match check_labor_rules(&shift, Some(&worker_id)) {
+        Ok(warnings) => warnings,
+        Err(error) => {
+            settle_escrow_after_change(&shift_id, &worker_id).await;
+            return Err(error);
+        }
+    };
+    
+    // Fill the seat; the shift is Approved once every seat is taken
+    shift.seats.push(ShiftSeat {
+        worker_id: worker_id.clone(),
+        status: SeatStatus::Assigned,
+        assigned_at: get_current_time(),
+        checked_in_at: None,
+        checked_out_at: None,
+        checkout_overdue_at: None,
+        work_history_id: None,
+    });
+    shift.updated_at = get_current_time();
+    if is_fully_staffed(&shift) {
+        transition_shift(&mut shift, ShiftStatus::Approved)?;
+    }
+    
+    // Update application status
+    application.status = ApplicationStatus::Approved;
+    APPLICATIONS.with(|apps| {
+        apps.borrow_mut().insert(app_key, application.clone())
+    });
+    
+    push_notification(
+        &application.worker_id,
+        NotificationType::ShiftApproved,
+        "Application approved",
+        format!("You have been approved for the {} shift on {}", shift.role, shift_local_date(&shift)),
+        Some(shift_id.clone()),
+    );
+    
+    save_shift(&shift);
+    record_compliance_warnings(&shift, Some(&worker_id), "approve_application", warnings);
+    
+    Ok(true)
+}
+
+#[update]
+fn reject_application(shift_id: ShiftId, worker_id: UserId) -> Result<bool, String> {
+    let caller = api::caller();
+    
+    // Verify caller owns the shift
+    let shift = SHIFTS.with(|shifts| {
+        shifts.borrow().get(&shift_id)
+            .ok_or_else(|| "Shift not found".to_string())
+    })?;
+    
+    let user_profile = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })?;
+    
+    if shift.business_id != user_profile.id {
+        return Err("Unauthorized: Cannot reject applications for another business's shift".to_string());
+    }
+    
+    // Update application status
+    let app_key = format!("{}_{}", shift_id, worker_id);
+    let mut application = APPLICATIONS.with(|apps| apps.borrow().get(&app_key))
+        .ok_or_else(|| "Application not found".to_string())?;
+    if application.status != ApplicationStatus::Pending {
+        return Err("Only pending applications can be rejected".to_string());
+    }
+    application.status = ApplicationStatus::Rejected;
+    APPLICATIONS.with(|apps| {
+        apps.borrow_mut().insert(app_key, application)
+    });
+    
+    push_notification(
+        &worker_id,
+        NotificationType::ShiftRejected,
+        "Application not accepted",
+        format!("Your application for the {} shift on {} was not accepted", shift.role, shift_local_date(&shift)),
+        Some(shift_id),
+    );
+    
+    Ok(true)
+}
+
+// Check-in / Check-out Functions
+#[update]
+fn check_in(shift_id: ShiftId) -> Result<Shift, String> {
+    let user_profile = get_caller_profile()?;
+    
+    let mut shift = SHIFTS.with(|shifts| {
+        shifts.borrow().get(&shift_id)
+            .ok_or_else(|| "Shift not found".to_string())
+    })?;
+    
+    let checkin_grace_minutes = read_config().checkin_grace_minutes.unwrap_or(DEFAULT_CHECKIN_GRACE_MINUTES);
+    check_in_seat(&mut shift, &user_profile.id, get_current_time(), checkin_grace_minutes)?;
+    save_shift(&shift);
+    
+    Ok(shift)
+}
+
+fn check_in_seat(shift: &mut Shift, worker_id: &UserId, now: i64, checkin_grace_minutes: u32) -> Result<(), String> {
+    let seat = seat_of(shift, worker_id)
+        .ok_or_else(|| "Unauthorized: Only an assigned worker can check in".to_string())?;
+    if seat.checked_in_at.is_some() {
+        return Err("Worker has already checked in".to_string());
+    }
+    // A seat on a partly filled shift can check in while it is still open;
+    // cancelled, expired or finished shifts take no more work
+    if !is_active_shift(shift) {
+        return Err(format!("Cannot check in to a {:?} shift", shift.status));
+    }
+    
+    if now < shift.starts_at - checkin_grace_minutes as i64 * 60_000 {
+        return Err(format!("Check-in opens {} minutes before the shift starts", checkin_grace_minutes));
+    }
+    if now >= shift.ends_at {
+        return Err("Shift has already ended".to_string());
+    }
+    
+    // An early check-in leaves the shift open to applicants until it starts;
+    // the business, another seat or the sweep may already have started it
+    if now >= shift.starts_at && shift.status != ShiftStatus::InProgress {
+        transition_shift(shift, ShiftStatus::InProgress)?;
+    }
+    if let Some(seat) = seat_of_mut(shift, worker_id) {
+        seat.status = SeatStatus::CheckedIn;
+        seat.checked_in_at = Some(now);
+    }
+    shift.updated_at = now;
+    Ok(())
+}
+
+#[update]
+async fn check_out(shift_id: ShiftId) -> Result<WorkHistory, String> {
+    let user_profile = get_caller_profile()?;
+    
+    let mut shift = SHIFTS.with(|shifts| {
+        shifts.borrow().get(&shift_id)
+            .ok_or_else(|| "Shift not found".to_string())
+    })?;
+    
+    if seat_of(&shift, &user_profile.id).is_none() {
+        return Err("Unauthorized: Only an assigned worker can check out".to_string());
+    }
+    
+    let work_history = complete_checked_in_seat(&mut shift, &user_profile.id, get_current_time())?;
+    save_shift(&shift);
+    settle_escrow_after_change(&shift_id, &user_profile.id).await;
+    
+    // Settlement may have stamped the entry as paid
+    Ok(WORK_HISTORY.with(|history| history.borrow().get(&work_history.id)).unwrap_or(work_history))
+}
+
+// Work History & DID Functions
+#[update]
+fn confirm_work_history(work_history_id: WorkHistoryId) -> Result<WorkHistory, String> {
+    let user_profile = get_caller_profile()?;
+    
+    let mut work_history = WORK_HISTORY.with(|history| {
+        history.borrow().get(&work_history_id)
+            .ok_or_else(|| "Work history not found".to_string())
+    })?;
+    
+    // Verify caller is the business who ran the shift
+    if user_profile.id != work_history.business_id {
+        return Err("Unauthorized: Only the business can confirm work history".to_string());
+    }
+    
+    if work_history.confirmed_at.is_some() {
+        return Err("Work history already confirmed".to_string());
+    }
+    
+    work_history.confirmed_at = Some(get_current_time());
+    
+    save_work_history(&work_history);
+    
+    Ok(work_history)
+}
+
+#[query]
+fn get_worker_history(worker_id: UserId, page: PageRequest) -> Result<Page<WorkHistory>, String> {
+    let sort_by = page.sort_by.unwrap_or(SortKey::Date);
+    paginate(worker_history(&worker_id), &page, SortDirection::Desc, |entry| {
+        let value = match sort_by {
+            SortKey::Date | SortKey::CreatedAt => entry.completed_at as i128,
+            SortKey::PayRate => entry.pay_earned.amount_minor as i128,
+        };
+        (value, entry.id.clone())
+    })
+}
+
+#[update]
+fn create_rating(mut rating: Rating) -> Result<bool, String> {
+    let caller = api::caller();
+    
+    // Verify caller is the business who created the shift
+    let user_profile = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&caller)
+            .ok_or_else(|| "User profile not found".to_string())
+    })?;
+    
+    if user_profile.id != rating.business_id {
+        return Err("Unauthorized: Only the business can create ratings".to_string());
+    }
+    
+    if !(1..=5).contains(&rating.rating) {
+        return Err("Rating must be between 1 and 5".to_string());
+    }
+    
+    // Ratings are per worker-seat: the worker must have completed a seat on
+    // this business's shift, and each seat is rated once
+    let shift = SHIFTS.with(|shifts| shifts.borrow().get(&rating.shift_id))
+        .ok_or_else(|| "Shift not found".to_string())?;
+    if shift.business_id != rating.business_id {
+        return Err("Unauthorized: Cannot rate work on another business's shift".to_string());
+    }
+    if !seat_of(&shift, &rating.worker_id).is_some_and(|seat| seat.status == SeatStatus::Completed) {
+        return Err("Worker has not completed a seat on this shift".to_string());
+    }
+    if worker_ratings(&rating.worker_id).iter().any(|existing| existing.shift_id == rating.shift_id) {
+        return Err("Worker has already been rated for this shift".to_string());
+    }
+    
+    // What the rating attests to is taken from the shift, not the caller
+    rating.business_name = business_name(&shift.business_id);
+    rating.role = shift.role.clone();
+    rating.date_worked = shift_local_date(&shift);
+    
+    // Identity and integrity fields are assigned by the canister
+    rating.id = generate_id();
+    rating.created_at = get_current_time();
+    rating.verification_hash = rating_hash(&rating);
+    rating.signature = None;
+    
+    save_rating(&rating);
+    
+    register_credential(CredentialKind::Rating, &rating.id, &rating.worker_id, &rating.business_id);
+    
+    // Update worker's DID document and average rating
+    DID_DOCUMENTS.with(|docs| {
+        let existing = docs.borrow().get(&rating.worker_id);
+        if let Some(mut did_doc) = existing {
+            did_doc.ratings.push(rating.id.clone());
+            docs.borrow_mut().insert(rating.worker_id.clone(), did_doc);
+        }
+    });
+    refresh_worker_aggregates(&rating.worker_id);
+    
+    Ok(true)
+}
+
+#[query]
+fn get_worker_ratings(worker_id: UserId, page: PageRequest) -> Result<Page<Rating>, String> {
+    if page.sort_by == Some(SortKey::PayRate) {
+        return Err("Ratings cannot be sorted by pay rate".to_string());
+    }
+    paginate(worker_ratings(&worker_id), &page, SortDirection::Desc, |rating| {
+        (rating.created_at as i128, rating.id.clone())
+    })
+}
+
+// Recomputes the SHA-256 of the stored record's canonical form and compares it
+// with the hash that was recorded when the entry was created.
+#[query]
+fn verify_work_history(work_history_id: WorkHistoryId) -> Result<bool, String> {
+    let entry = WORK_HISTORY.with(|history| {
+        history.borrow().get(&work_history_id)
+            .ok_or_else(|| "Work history not found".to_string())
+    })?;
+    
+    Ok(work_history_hash(&entry) == entry.verification_hash)
+}
+
+#[query]
+fn verify_rating(rating_id: RatingId) -> Result<bool, String> {
+    let rating = RATINGS.with(|ratings| {
+        ratings.borrow().get(&rating_id)
+            .ok_or_else(|| "Rating not found".to_string())
+    })?;
+    
+    Ok(rating_hash(&rating) == rating.verification_hash)
+}
+
+#[query]
+fn get_worker_did(worker_id: UserId) -> Result<DIDDocument, String> {
+    DID_DOCUMENTS.with(|docs| {
+        docs.borrow().get(&worker_id)
+            .ok_or_else(|| "DID document not found".to_string())
+    })
+}
+
+#[query]
+fn export_worker_did(worker_id: UserId) -> Result<DIDExport, String> {
+    let document = get_worker_did(worker_id)?;
+    
+    let work_history: Vec<WorkHistory> = WORK_HISTORY.with(|history| {
+        let history = history.borrow();
+        document.work_history.iter()
+            .filter_map(|id| history.get(id))
+            .collect()
+    });
+    
+    let ratings: Vec<Rating> = RATINGS.with(|ratings| {
+        let ratings = ratings.borrow();
+        document.ratings.iter()
+            .filter_map(|id| ratings.get(id))
+            .collect()
+    });
+    
+    Ok(DIDExport {
+        document,
+        work_history,
+        ratings,
+        signing_key: signing_key(),
+    })
+}
+
+// W3C DID Core document for the worker plus their work history and ratings as
+// Verifiable Credentials, serialized as JSON-LD text for platforms that do not
+// speak Candid.
+#[query]
+fn export_worker_did_jsonld(worker_id: UserId) -> Result<String, String> {
+    let export = export_worker_did(worker_id.clone())?;
+    let principal = find_user_principal(&worker_id)
+        .ok_or_else(|| "Worker principal not found".to_string())?;
+    
+    let subject_did = principal_did(&principal);
+    let issuer_did = principal_did(&api::id());
+    let key_reference = signing_key_reference();
+    
+    let verification_methods: Vec<serde_json::Value> = export.signing_key.iter()
+        .map(|key| json!({
+            "id": key_reference,
+            "type": "EcdsaSecp256k1VerificationKey2019",
+            "controller": issuer_did,
+            "publicKeyHex": key.public_key,
+        }))
+        .collect();
+    let assertion_methods: Vec<&str> = verification_methods.iter()
+        .map(|_| key_reference.as_str())
+        .collect();
+    
+    let mut did_document = json!({
+        "@context": [DID_CONTEXT, SECP256K1_CONTEXT],
+        "id": subject_did,
+        "controller": subject_did,
+        "alsoKnownAs": [format!("urn:flexwage:worker:{}", worker_id)],
+        "verificationMethod": verification_methods,
+        "assertionMethod": assertion_methods,
+        "service": [{
+            "id": format!("{}#flexwage", subject_did),
+            "type": "FlexWageWorkHistory",
+            "serviceEndpoint": format!("https://{}.icp0.io", api::id().to_text()),
+        }],
+    });
+    if !export.document.signature.is_empty() {
+        did_document["proof"] = signature_proof(
+            &export.document.signature,
+            &sha256_hex(canonical_did_document(&export.document).as_bytes()),
+            export.document.updated_at,
+        );
+    }
+    
+    let credentials: Vec<serde_json::Value> = export.work_history.iter()
+        .map(|entry| work_history_credential(entry, &subject_did))
+        .chain(export.ratings.iter().map(|rating| rating_credential(rating, &subject_did)))
+        .collect();
+    
+    Ok(json!({
+        "didDocument": did_document,
+        "verifiableCredentials": credentials,
+    })
+    .to_string())
+}
+
+#[query]
+fn get_signing_public_key() -> Result<SigningKey, String> {
+    signing_key().ok_or_else(|| "Signing key not initialized yet".to_string())
+}
+
+// Signs every unsigned work history and rating entry referenced by the DID
+// document, then the document itself. Signatures are secp256k1 over the
+// SHA-256 of each record's canonical form (see the README).
+#[update]
+async fn sign_worker_did(worker_id: UserId) -> Result<DIDDocument, String> {
+    let user_profile = get_caller_profile()?;
+    if user_profile.id != worker_id && !api::is_controller(&api::caller()) {
+        return Err("Unauthorized: Cannot sign another worker's DID document".to_string());
+    }
+    
+    let document = get_worker_did(worker_id.clone())?;
+    ensure_signing_public_key().await?;
+    
+    for id in &document.work_history {
+        let entry = WORK_HISTORY.with(|history| history.borrow().get(id));
+        let Some(entry) = entry.filter(|e| e.signature.is_none()) else { continue };
+        let signature = sign_digest(sha256(canonical_work_history(&entry).as_bytes())).await?;
+        
+        // Only attach the signature if the record did not change while we awaited
+        WORK_HISTORY.with(|history| {
+            let current = history.borrow().get(id);
+            if let Some(mut current) = current.filter(|c| c.verification_hash == entry.verification_hash) {
+                current.signature = Some(signature);
+                history.borrow_mut().insert(id.clone(), current);
+            }
+        });
+    }
+    
+    for id in &document.ratings {
+        let rating = RATINGS.with(|ratings| ratings.borrow().get(id));
+        let Some(rating) = rating.filter(|r| r.signature.is_none()) else { continue };
+        let signature = sign_digest(sha256(canonical_rating(&rating).as_bytes())).await?;
+        
+        RATINGS.with(|ratings| {
+            let current = ratings.borrow().get(id);
+            if let Some(mut current) = current.filter(|c| c.verification_hash == rating.verification_hash) {
+                current.signature = Some(signature);
+                ratings.borrow_mut().insert(id.clone(), current);
+            }
+        });
+    }
+    
+    // Re-signing an unchanged document would only spend cycles
+    let document = get_worker_did(worker_id.clone())?;
+    let canonical = canonical_did_document(&document);
+    let digest = sha256(canonical.as_bytes());
+    if document.signed_digest.as_deref() == Some(to_hex(&digest).as_str()) {
+        return Ok(document);
+    }
+    let signature = sign_digest(digest).await?;
+    
+    DID_DOCUMENTS.with(|docs| {
+        let mut current = docs.borrow().get(&worker_id)
+            .ok_or_else(|| "DID document not found".to_string())?;
+        if canonical_did_document(&current) != canonical {
+            return Err("DID document changed while signing, please retry".to_string());
+        }
+        current.signature = signature;
+        current.signed_digest = Some(to_hex(&digest));
+        docs.borrow_mut().insert(worker_id.clone(), current.clone());
+        Ok(current)
+    })
+}
+
+// Verifiable Presentation Functions
+// Builds a presentation from a worker-selected subset of their records (or an
+// aggregate claim over them), bound to the verifier's nonce and expiry, and
+// signs it with the canister key.
+#[update]
+async fn create_presentation(request: PresentationRequest) -> Result<Presentation, String> {
+    let caller = api::caller();
+    let worker_id = PRINCIPAL_TO_USERID.with(|mapping| mapping.borrow().get(&caller))
+        .ok_or_else(|| "User profile not found".to_string())?;
+    
+    let now = get_current_time();
+    if request.expires_at <= now {
+        return Err("Presentation expiry must be in the future".to_string());
+    }
+    if request.verifier_nonce.is_empty() || request.verifier_nonce.len() > MAX_NONCE_LENGTH {
+        return Err(format!("Verifier nonce must be 1-{} characters", MAX_NONCE_LENGTH));
+    }
+    if request.work_history_ids.is_empty() && request.rating_ids.is_empty() && request.aggregate.is_none() {
+        return Err("Nothing selected for disclosure".to_string());
+    }
+    
+    let holder = principal_did(&caller);
+    let mut credentials = Vec::new();
+    
+    for id in &request.work_history_ids {
+        let entry = WORK_HISTORY.with(|history| history.borrow().get(id))
+            .filter(|entry| entry.worker_id == worker_id)
+            .ok_or_else(|| format!("Work history {} not found for this worker", id))?;
+        credentials.push(work_history_credential(&entry, &holder));
+    }
+    
+    for id in &request.rating_ids {
+        let rating = RATINGS.with(|ratings| ratings.borrow().get(id))
+            .filter(|rating| rating.worker_id == worker_id)
+            .ok_or_else(|| format!("Rating {} not found for this worker", id))?;
+        credentials.push(rating_credential(&rating, &holder));
+    }
+    
+    let presentation_id = generate_id();
+    
+    if let Some(criteria) = &request.aggregate {
+        let claim = aggregate_claim(&worker_id, criteria)?;
+        credentials.push(json!({
+            "@context": [VC_CONTEXT, { "@vocab": FLEXWAGE_VOCAB }],
+            "id": format!("urn:flexwage:work-summary:{}", presentation_id),
+            "type": ["VerifiableCredential", "WorkSummaryCredential"],
+            "issuer": principal_did(&api::id()),
+            "issuanceDate": format_rfc3339(now),
+            "credentialSubject": {
+                "id": holder,
+                "workSummary": claim,
+            },
+        }));
+    }
+    
+    let mut document = json!({
+        "@context": [VC_CONTEXT, { "@vocab": FLEXWAGE_VOCAB }],
+        "id": format!("urn:flexwage:presentation:{}", presentation_id),
+        "type": ["VerifiablePresentation"],
+        "holder": holder,
+        "challenge": request.verifier_nonce,
+        "expirationDate": format_rfc3339(request.expires_at),
+        "verifiableCredential": credentials,
+    });
+    
+    // Counted before signing, so concurrent calls cannot all slip under the limit
+    let window_start = (now - 3_600_000).max(0) as u64;
+    let recent = PRESENTATIONS_BY_WORKER.with(|index| {
+        index.borrow().range((worker_id.clone(), window_start, String::new())..)
+            .take_while(|((owner, _, _), _)| *owner == worker_id)
+            .count()
+    });
+    if recent >= MAX_PRESENTATIONS_PER_HOUR {
+        return Err(format!("At most {} presentations can be created per hour", MAX_PRESENTATIONS_PER_HOUR));
+    }
+    PRESENTATIONS_BY_WORKER.with(|index| {
+        let mut index = index.borrow_mut();
+        let expired: Vec<(UserId, u64, String)> = index
+            .range((worker_id.clone(), 0, String::new())..(worker_id.clone(), window_start, String::new()))
+            .map(|(key, _)| key)
+            .collect();
+        for key in &expired {
+            index.remove(key);
+        }
+        index.insert((worker_id.clone(), now.max(0) as u64, presentation_id.clone()), ());
+    });
+    
+    ensure_signing_public_key().await?;
+    let digest = presentation_digest(&document);
+    let signature = sign_digest(digest).await?;
+    let digest = to_hex(&digest);
+    
+    let mut proof = signature_proof(&signature, &digest, now);
+    proof["proofPurpose"] = json!("authentication");
+    proof["challenge"] = json!(request.verifier_nonce);
+    document["proof"] = proof;
+    
+    let record = PresentationRecord {
+        id: presentation_id.clone(),
+        worker_id,
+        verifier_nonce: request.verifier_nonce.clone(),
+        expires_at: request.expires_at,
+        created_at: now,
+        digest: digest.clone(),
+        signature: signature.clone(),
+    };
+    PRESENTATIONS.with(|presentations| {
+        presentations.borrow_mut().insert(presentation_id.clone(), record)
+    });
+    
+    Ok(Presentation {
+        id: presentation_id,
+        holder,
+        verifier_nonce: request.verifier_nonce,
+        expires_at: request.expires_at,
+        created_at: now,
+        digest,
+        signature,
+        document: document.to_string(),
+    })
+}
+
+// Checks a presentation document against the canister's record of it: the
+// recomputed digest and signature must match, the nonce must be the one the
+// verifier supplied and the presentation must not have expired.
+#[query]
+fn verify_presentation(document: String, verifier_nonce: String) -> PresentationVerification {
+    let invalid = |reason: &str| PresentationVerification {
+        valid: false,
+        holder: None,
+        expires_at: None,
+        reason: Some(reason.to_string()),
+    };
+    
+    let Ok(presentation) = serde_json::from_str::<serde_json::Value>(&document) else {
+        return invalid("Presentation is not valid JSON");
+    };
+    let Some(presentation_id) = presentation["id"].as_str()
+        .and_then(|id| id.strip_prefix("urn:flexwage:presentation:")) else {
+        return invalid("Presentation id missing");
+    };
+    let Some(record) = PRESENTATIONS.with(|presentations| presentations.borrow().get(&presentation_id.to_string())) else {
+        return invalid("Presentation was not issued by this canister");
+    };
+    
+    let digest = to_hex(&presentation_digest(&presentation));
+    let reason = if digest != record.digest || presentation["proof"]["digest"].as_str() != Some(&record.digest) {
+        Some("Presentation content does not match its proof")
+    } else if presentation["proof"]["proofValue"].as_str() != Some(&record.signature) {
+        Some("Presentation signature does not match")
+    } else if record.verifier_nonce != verifier_nonce {
+        Some("Presentation was issued for a different nonce")
+    } else if record.expires_at <= get_current_time() {
+        Some("Presentation has expired")
+    } else if presentation["verifiableCredential"].as_array().is_some_and(|credentials| {
+        credentials.iter()
+            .filter_map(|credential| credential["id"].as_str())
+            .any(is_revoked)
+    }) {
+        Some("Presentation contains a revoked credential")
+    } else {
+        None
+    };
+    
+    PresentationVerification {
+        valid: reason.is_none(),
+        holder: presentation["holder"].as_str().map(str::to_string),
+        expires_at: Some(record.expires_at),
+        reason: reason.map(str::to_string),
+    }
+}
+
+// Escrow Functions
+#[query]
+fn get_shift_escrow(shift_id: ShiftId, worker_id: UserId) -> Result<Escrow, String> {
+    ESCROWS.with(|escrows| {
+        escrows.borrow().get(&escrow_key(&shift_id, &worker_id))
+            .ok_or_else(|| "Escrow not found".to_string())
+    })
+}
+
+// Every seat escrow of a shift, visible to the business that owns it
+#[query]
+fn get_shift_escrows(shift_id: ShiftId) -> Result<Vec<Escrow>, String> {
+    get_owned_shift(&shift_id, "view escrows for")?;
+    Ok(shift_escrows(&shift_id))
+}
+
+// Retries settlement of a funded escrow whose payout or refund failed earlier
+#[update]
+async fn settle_shift_escrow(shift_id: ShiftId, worker_id: UserId) -> Result<Escrow, String> {
+    let escrow = get_shift_escrow(shift_id.clone(), worker_id.clone())?;
+    let caller = api::caller();
+    let caller_user_id = PRINCIPAL_TO_USERID.with(|mapping| mapping.borrow().get(&caller));
+    let is_party = caller_user_id.as_ref()
+        .is_some_and(|id| *id == escrow.business_id || *id == escrow.worker_id);
+    if !is_party && !api::is_controller(&caller) {
+        return Err("Unauthorized: Cannot settle another party's escrow".to_string());
+    }
+    
+    settle_escrow(&shift_id, &worker_id).await?
+        .ok_or_else(|| "Escrow not found".to_string())
+}
+
+// Early Wage Access Functions
+// Pays the calling worker part of their completed, unpaid earnings from the
+// canister's advance pool. The amount plus fee is allocated against the oldest
+// eligible entries and recovered when the business pays for them.
+#[update]
+async fn request_advance(amount: Money) -> Result<Advance, String> {
+    let user_profile = get_caller_profile()?;
+    if user_profile.user_type != UserType::Worker {
+        return Err("Only workers can request advances".to_string());
+    }
+    let ledger = read_config().ledger_canister_id
+        .ok_or_else(|| "Early wage access requires a configured ledger".to_string())?;
+    if amount.amount_minor == 0 {
+        return Err("Advance amount must be positive".to_string());
+    }
+    
+    let (_, fee_bps) = advance_terms();
+    let fee = advance_fee(&amount, fee_bps);
+    let mut debt = amount.amount_minor + fee.amount_minor;
+    
+    let capacity = advance_capacity(&user_profile.id, &amount.currency);
+    let available: u64 = capacity.iter().map(|(_, remaining)| remaining).sum();
+    if debt > available {
+        return Err(format!(
+            "Advance plus fee ({} {}) exceeds the available balance of {} {}",
+            debt, amount.currency, available, amount.currency
+        ));
+    }
+    
+    let mut allocations = Vec::new();
+    for (entry, remaining) in capacity {
+        if debt == 0 {
+            break;
+        }
+        let drawn = remaining.min(debt);
+        if drawn > 0 {
+            allocations.push(AdvanceAllocation {
+                work_history_id: entry.id,
+                amount_minor: drawn,
+                repaid_at: None,
+            });
+            debt -= drawn;
+        }
+    }
+    
+    // Recorded before the payout so concurrent requests see the allocations
+    let mut advance = Advance {
+        id: generate_id(),
+        worker_id: user_profile.id.clone(),
+        amount: amount.clone(),
+        fee,
+        allocations,
+        status: AdvanceStatus::Pending,
+        payout_block: None,
+        requested_at: get_current_time(),
+        repaid_at: None,
+        last_error: None,
+    };
+    save_advance(&advance);
+    
+    let result = async {
+        let fee = ledger_fee(ledger).await?;
+        let units = to_ledger_units(&amount);
+        if units <= fee {
+            return Err("Advance amount does not cover the ledger fee".to_string());
+        }
+        let to = Account { owner: api::caller(), subaccount: None };
+        ledger_transfer(ledger, None, to, units - fee).await
+    }
+    .await;
+    
+    match result {
+        Ok(block) => {
+            advance.status = AdvanceStatus::Paid;
+            advance.payout_block = Some(block);
+        }
+        Err(error) => {
+            advance.status = AdvanceStatus::Failed;
+            advance.last_error = Some(error);
+        }
+    }
+    save_advance(&advance);
+    
+    match advance.status {
+        AdvanceStatus::Paid => Ok(advance),
+        _ => Err(advance.last_error.unwrap_or_default()),
+    }
+}
+
+#[query]
+fn get_advance_balance(currency: Option<String>) -> Result<AdvanceBalance, String> {
+    let user_profile = get_caller_profile()?;
+    let currency = currency.unwrap_or_else(default_currency);
+    Ok(advance_balance(&user_profile.id, &currency))
+}
+
+// The caller's advances, newest first
+#[query]
+fn get_advance_history(page: PageRequest) -> Result<Page<Advance>, String> {
+    let user_profile = get_caller_profile()?;
+    advance_history(&user_profile.id, &page)
+}
+
+fn advance_history(worker_id: &UserId, page: &PageRequest) -> Result<Page<Advance>, String> {
+    let (keys, next_cursor) = ADVANCES_BY_WORKER.with(|index| {
+        page_index(&index.borrow(), worker_id, page, SortDirection::Desc)
+    })?;
+    let items = ADVANCES.with(|advances| {
+        let advances = advances.borrow();
+        keys.iter().filter_map(|((_, _, id), _)| advances.get(id)).collect()
+    });
+    Ok(Page { items, next_cursor, total: None })
+}
+
+// Business pays for completed work that was not escrowed. The pay is pulled
+// into the worker's seat escrow subaccount with an ICRC-2 allowance and settled
+// like any other escrow, netting out the worker's advances against it.
+#[update]
+async fn settle_work_history(work_history_id: WorkHistoryId) -> Result<Escrow, String> {
+    let user_profile = get_caller_profile()?;
+    let entry = WORK_HISTORY.with(|history| history.borrow().get(&work_history_id))
+        .ok_or_else(|| "Work history not found".to_string())?;
+    if entry.business_id != user_profile.id {
+        return Err("Unauthorized: Only the business can pay for this work".to_string());
+    }
+    if entry.settled_at.is_some() {
+        return Err("Work history has already been paid".to_string());
+    }
+    let ledger = read_config().ledger_canister_id
+        .ok_or_else(|| "Ledger canister is not configured".to_string())?;
+    let shift = SHIFTS.with(|shifts| shifts.borrow().get(&entry.shift_id))
+        .ok_or_else(|| "Shift not found".to_string())?;
+    
+    fund_shift_escrow(ledger, &shift, &entry.worker_id, to_ledger_units(&entry.pay_earned)).await?;
+    settle_escrow(&shift.id, &entry.worker_id).await?
+        .ok_or_else(|| "Escrow not found".to_string())
+}
+
+// Revocation Functions
+// The issuing business (or a canister controller acting as admin) can revoke
+// a work history or rating credential, e.g. after a dispute.
+#[update]
+fn revoke_credential(credential_id: String, reason: String) -> Result<CredentialStatus, String> {
+    let caller = api::caller();
+    
+    if reason.trim().is_empty() || reason.len() > MAX_REVOCATION_REASON_LENGTH {
+        return Err(format!("Revocation reason must be 1-{} characters", MAX_REVOCATION_REASON_LENGTH));
+    }
+    
+    let mut status = CREDENTIAL_STATUS.with(|statuses| {
+        statuses.borrow().get(&credential_id)
+            .ok_or_else(|| "Credential not found".to_string())
+    })?;
+    
+    let caller_user_id = PRINCIPAL_TO_USERID.with(|mapping| mapping.borrow().get(&caller));
+    if caller_user_id.as_ref() != Some(&status.issuer_business_id) && !api::is_controller(&caller) {
+        return Err("Unauthorized: Only the issuing business can revoke this credential".to_string());
+    }
+    
+    if status.revocation.is_some() {
+        return Err("Credential already revoked".to_string());
+    }
+    
+    status.revocation = Some(Revocation {
+        reason,
+        revoked_by: caller,
+        revoked_at: get_current_time(),
+    });
+    
+    CREDENTIAL_STATUS.with(|statuses| {
+        statuses.borrow_mut().insert(credential_id, status.clone())
+    });
+    
+    refresh_worker_aggregates(&status.worker_id);
+    
+    Ok(status)
+}
+
+#[query]
+fn get_credential_status(credential_id: String) -> Result<CredentialStatus, String> {
+    CREDENTIAL_STATUS.with(|statuses| {
+        statuses.borrow().get(&credential_id)
+            .ok_or_else(|| "Credential not found".to_string())
+    })
+}
+
+#[query]
+fn get_revocation_status_list() -> RevocationStatusList {
+    CREDENTIAL_STATUS.with(|statuses| {
+        let statuses = statuses.borrow();
+        let length = statuses.len();
+        let mut bitstring = vec![0u8; length.div_ceil(8) as usize];
+        let mut updated_at = 0;
+        
+        for (_, status) in statuses.iter() {
+            if let Some(revocation) = &status.revocation {
+                let index = status.status_index as usize;
+                bitstring[index / 8] |= 0x80 >> (index % 8);
+                updated_at = updated_at.max(revocation.revoked_at);
+            }
+        }
+        
+        RevocationStatusList {
+            length,
+            bitstring,
+            updated_at,
+        }
+    })
+}
+
+// Notification Functions
+// Workflow notifications are generated by the canister; this is only for
+// operator announcements, so it is restricted to canister controllers
+#[update]
+fn create_notification(notification: Notification) -> Result<bool, String> {
+    if !api::is_controller(&api::caller()) {
+        return Err("Unauthorized: Only canister controllers can create notifications".to_string());
+    }
+    
+    push_notification(
+        &notification.user_id,
+        notification.notification_type,
+        &notification.title,
+        notification.message,
+        notification.related_shift_id,
+    );
+    Ok(true)
+}
+
+// The caller's inbox, newest first unless direction is Asc. Notifications
+// only sort by creation time; Date is accepted as a synonym.
+#[query]
+fn get_notifications(page: PageRequest) -> Result<NotificationPage, String> {
+    let user_profile = get_caller_profile()?;
+    if page.sort_by == Some(SortKey::PayRate) {
+        return Err("Notifications cannot be sorted by pay rate".to_string());
+    }
+    let limit = page_limit(page.limit);
+    let cursor = match &page.cursor {
+        Some(cursor) => {
+            let (created_at, id) = parse_cursor(cursor)?;
+            let created_at = u64::try_from(created_at).map_err(|_| "Invalid cursor".to_string())?;
+            Some((user_profile.id.clone(), Reverse(created_at), id))
+        }
+        None => None,
+    };
+    let first = (user_profile.id.clone(), Reverse(u64::MAX), String::new());
+    
+    // The index is already newest first, so the default order reads a single
+    // range. Oldest first walks the caller's keys, bounded by retention.
+    let (keys, total): (Vec<NotificationKey>, u64) = NOTIFICATION_INDEX.with(|index| {
+        let index = index.borrow();
+        let owned = || index.range(first.clone()..)
+            .take_while(|((owner, _, _), _)| *owner == user_profile.id)
+            .map(|(key, _)| key);
+        let total = owned().count() as u64;
+        let keys = match page.direction.unwrap_or(SortDirection::Desc) {
+            SortDirection::Desc => {
+                let start = match &cursor {
+                    Some(cursor) => std::ops::Bound::Excluded(cursor.clone()),
+                    None => std::ops::Bound::Included(first.clone()),
+                };
+                index.range((start, std::ops::Bound::Unbounded))
+                    .take_while(|((owner, _, _), _)| *owner == user_profile.id)
+                    .take(limit + 1)
+                    .map(|(key, _)| key)
+                    .collect()
+            }
+            SortDirection::Asc => {
+                let mut keys: Vec<NotificationKey> = owned().collect();
+                keys.reverse();
+                if let Some(cursor) = &cursor {
+                    keys.retain(|key| key < cursor);
+                }
+                keys.truncate(limit + 1);
+                keys
+            }
+        };
+        (keys, total)
+    });
+    
+    let next_cursor = if keys.len() > limit {
+        keys.get(limit - 1).map(|(_, Reverse(created_at), id)| format!("{}:{}", created_at, id))
+    } else {
+        None
+    };
+    let items = NOTIFICATIONS.with(|notifications| {
+        let notifications = notifications.borrow();
+        keys.iter()
+            .take(limit)
+            .filter_map(|(_, _, id)| notifications.get(id))
+            .collect()
+    });
+    
+    Ok(NotificationPage {
+        items,
+        next_cursor,
+        total: Some(total),
+        unread_count: unread_notification_count(&user_profile.id),
+    })
+}
+
+#[query]
+fn get_unread_notification_count() -> Result<u64, String> {
+    let user_profile = get_caller_profile()?;
+    Ok(unread_notification_count(&user_profile.id))
+}
+
+#[update]
+fn mark_notification_read(notification_id: String) -> Result<bool, String> {
+    let mut notification = get_caller_notification(&notification_id)?;
+    if !notification.is_read {
+        notification.is_read = true;
+        save_notification(notification);
+    }
+    Ok(true)
+}
+
+// Returns how many notifications were marked
+#[update]
+fn mark_all_notifications_read() -> Result<u64, String> {
+    let user_profile = get_caller_profile()?;
+    let start = (user_profile.id.clone(), Reverse(u64::MAX), String::new());
+    let unread: Vec<String> = NOTIFICATION_INDEX.with(|index| {
+        index.borrow().range(start..)
+            .take_while(|((owner, _, _), _)| *owner == user_profile.id)
+            .filter(|(_, is_read)| !is_read)
+            .map(|((_, _, id), _)| id)
+            .collect()
+    });
+    
+    let mut marked = 0;
+    for id in unread {
+        if let Some(mut notification) = NOTIFICATIONS.with(|notifications| notifications.borrow().get(&id)) {
+            notification.is_read = true;
+            save_notification(notification);
+            marked += 1;
+        }
+    }
+    Ok(marked)
+}
+
+#[update]
+fn delete_notification(notification_id: String) -> Result<bool, String> {
+    let notification = get_caller_notification(&notification_id)?;
+    remove_notification(&notification);
+    Ok(true)
+}
+
+// Outbound Delivery Functions
+// Strips the relay's response down to its status so every replica agrees on it
+#[query]
+fn transform_delivery_response(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+#[query]
+fn get_delivery_dead_letters() -> Result<Vec<DeliveryJob>, String> {
+    if !api::is_controller(&api::caller()) {
+        return Err("Unauthorized: Only canister controllers can inspect failed deliveries".to_string());
+    }
+    Ok(DEAD_LETTERS.with(|dead_letters| {
+        dead_letters.borrow().iter().map(|(_, job)| job).collect()
+    }))
+}
+
+// Moves a dead-lettered delivery back onto the queue with a fresh set of attempts
+#[update]
+fn retry_dead_letter(notification_id: String) -> Result<bool, String> {
+    if !api::is_controller(&api::caller()) {
+        return Err("Unauthorized: Only canister controllers can retry deliveries".to_string());
+    }
+    let mut job = DEAD_LETTERS.with(|dead_letters| dead_letters.borrow_mut().remove(&notification_id))
+        .ok_or_else(|| "Dead letter not found".to_string())?;
+    job.attempts = 0;
+    job.next_attempt_at = get_current_time();
+    enqueue_delivery(job);
+    Ok(true)
+}
+
+// Notification Retention Functions
+#[query]
+fn get_notification_retention() -> NotificationRetention {
+    notification_retention()
+}
+
+// Controllers tune retention at runtime; the next maintenance run applies it
+#[update]
+fn update_notification_retention(retention: NotificationRetention) -> Result<NotificationRetention, String> {
+    if !api::is_controller(&api::caller()) {
+        return Err("Unauthorized: Only canister controllers can change notification retention".to_string());
+    }
+    if retention.retention_days == 0 || retention.max_per_user == 0 {
+        return Err("Retention window and per-user cap must be positive".to_string());
+    }
+    update_config(|config| {
+        config.notification_retention_days = Some(retention.retention_days);
+        config.max_notifications_per_user = Some(retention.max_per_user);
+    });
+    Ok(notification_retention())
+}
+
+// Notification Preference Functions
+#[query]
+fn get_notification_preferences() -> Result<NotificationPreferences, String> {
+    let user_profile = get_caller_profile()?;
+    Ok(notification_preferences(&user_profile.id))
+}
+
+#[update]
+fn update_notification_preferences(mut preferences: NotificationPreferences) -> Result<NotificationPreferences, String> {
+    let user_profile = get_caller_profile()?;
+    
+    if let Some(quiet_hours) = &preferences.quiet_hours {
+        if quiet_hours.start_minute >= 24 * 60 || quiet_hours.end_minute >= 24 * 60 {
+            return Err("Quiet hours must be given in minutes since midnight (0-1439)".to_string());
+        }
+        if quiet_hours.start_minute == quiet_hours.end_minute {
+            return Err("Quiet hours must not start and end at the same minute".to_string());
+        }
+        if !is_valid_utc_offset(quiet_hours.utc_offset_minutes) {
+            return Err("UTC offset must be within +/-18 hours".to_string());
+        }
+    }
+    if preferences.muted_types.iter().any(|muted| preferences.urgent_types.contains(muted)) {
+        return Err("A notification type cannot be both muted and urgent".to_string());
+    }
+    dedup_notification_types(&mut preferences.muted_types);
+    dedup_notification_types(&mut preferences.urgent_types);
+    preferences.updated_at = get_current_time();
+    
+    NOTIFICATION_PREFERENCES.with(|store| {
+        store.borrow_mut().insert(user_profile.id, preferences.clone())
+    });
+    
+    Ok(preferences)
+}
+
+// Utility Functions
+// One-off repair: rebuilds the secondary indexes from the primary maps
+#[update]
+fn rebuild_secondary_indexes() -> Result<bool, String> {
+    if !api::is_controller(&api::caller()) {
+        return Err("Unauthorized: Only canister controllers can rebuild indexes".to_string());
+    }
+    rebuild_indexes();
+    Ok(true)
+}
+
+#[query]
+fn get_caller_principal() -> Principal {
+    api::caller()
+}
+
+#[query]
+fn health_check() -> bool {
+    true
+}
+
+// Rewrites shifts and work history decoded from older layouts. Work history
+// migrated from f32 pay can stop matching its verification hash; the stored
+// hash and any signature over it are kept as they are, so verify_work_history
+// reports those entries as unverified. How many there are is logged once.
+fn migrate_legacy_records() {
+    let shifts: Vec<(ShiftId, Shift)> = SHIFTS.with(|shifts| shifts.borrow().iter().collect());
+    SHIFTS.with(|store| {
+        let mut store = store.borrow_mut();
+        for (id, shift) in shifts {
+            store.insert(id, shift);
+        }
+    });
+    
+    let history: Vec<(WorkHistoryId, WorkHistory)> = WORK_HISTORY.with(|history| history.borrow().iter().collect());
+    let mismatched = history.iter()
+        .filter(|(_, entry)| work_history_hash(entry) != entry.verification_hash)
+        .count();
+    WORK_HISTORY.with(|store| {
+        let mut store = store.borrow_mut();
+        for (id, entry) in history {
+            store.insert(id, entry);
+        }
+    });
+    if mismatched > 0 {
+        ic_cdk::println!("{} migrated work history entries no longer match their verification hash", mismatched);
+    }
+    
+    // Escrows used to be keyed by shift alone; the funds stay in the
+    // subaccount recorded on each escrow, so only the key moves
+    let escrows: Vec<(String, Escrow)> = ESCROWS.with(|escrows| escrows.borrow().iter().collect());
+    ESCROWS.with(|store| {
+        let mut store = store.borrow_mut();
+        for (key, escrow) in escrows {
+            let seat_key = escrow_key(&escrow.shift_id, &escrow.worker_id);
+            if key != seat_key {
+                store.remove(&key);
+                store.insert(seat_key, escrow);
+            }
+        }
+    });
+}
+
+// Outbound Delivery
+// A timer drains due jobs from DELIVERY_QUEUE and POSTs each notification as
+// JSON to the configured relay, which forwards it by email, SMS or webhook.
+// Every replica makes the outcall, so the relay must deduplicate on the
+// Idempotency-Key header.
+fn enqueue_delivery(job: DeliveryJob) {
+    DELIVERY_QUEUE.with(|queue| {
+        queue.borrow_mut().insert(delivery_key(&job), job)
+    });
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    
+    let mut inner = Sha256::new();
+    inner.update(block.map(|byte| byte ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+fn delivery_payload(notification: &Notification) -> serde_json::Value {
+    let profile = find_user_principal(&notification.user_id)
+        .and_then(|principal| USER_PROFILES.with(|profiles| profiles.borrow().get(&principal)));
+    json!({
+        "id": notification.id,
+        "user_id": notification.user_id,
+        "type": format!("{:?}", notification.notification_type),
+        "title": notification.title,
+        "message": notification.message,
+        "related_shift_id": notification.related_shift_id,
+        "created_at": format_rfc3339(notification.created_at),
+        "recipient": {
+            "name": profile.as_ref().map(|profile| profile.name.clone()),
+            "email": profile.as_ref().map(|profile| profile.email.clone()),
+            "phone": profile.as_ref().and_then(|profile| profile.phone.clone()),
+        },
+    })
+}
+
+async fn send_delivery(relay_url: &str, secret: Option<&str>, notification: &Notification) -> Result<(), String> {
+    let body = delivery_payload(notification).to_string();
+    let timestamp = (get_current_time() / 1000).to_string();
+    
+    let mut headers = vec![
+        HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() },
+        HttpHeader { name: "Idempotency-Key".to_string(), value: notification.id.clone() },
+        HttpHeader { name: "X-FlexWage-Timestamp".to_string(), value: timestamp.clone() },
+    ];
+    // Signed over "<timestamp>.<body>" so the relay can reject replays
+    if let Some(secret) = secret {
+        let signature = hmac_sha256(secret.as_bytes(), format!("{}.{}", timestamp, body).as_bytes());
+        headers.push(HttpHeader {
+            name: "X-FlexWage-Signature".to_string(),
+            value: format!("sha256={}", to_hex(&signature)),
+        });
+    }
+    
+    let request = CanisterHttpRequestArgument {
+        url: relay_url.to_string(),
+        max_response_bytes: Some(DELIVERY_MAX_RESPONSE_BYTES),
+        method: HttpMethod::POST,
+        headers,
+        body: Some(body.into_bytes()),
+        transform: Some(TransformContext::from_name("transform_delivery_response".to_string(), Vec::new())),
+    };
+    let (response,) = http_request(request, DELIVERY_CYCLES)
+        .await
+        .map_err(|(code, msg)| format!("Outcall failed: {:?} {}", code, msg))?;
+    
+    if response.status >= 200u64 && response.status < 300u64 {
+        Ok(())
+    } else {
+        Err(format!("Relay responded with status {}", response.status))
+    }
+}
+
+fn delivery_backoff_ms(attempts: u32) -> i64 {
+    DELIVERY_BACKOFF_BASE_MS
+        .saturating_mul(1i64 << attempts.saturating_sub(1).min(20))
+        .min(DELIVERY_BACKOFF_MAX_MS)
+}
+
+fn delivery_key(job: &DeliveryJob) -> (u64, String) {
+    (job.next_attempt_at.max(0) as u64, job.notification_id.clone())
+}
+
+// Counts the attempt and moves the job to its retry time before the outcall,
+// so a trap in the callback leaves it queued for a retry rather than lost
+fn claim_delivery(key: &(u64, String), now: i64) -> Option<DeliveryJob> {
+    DELIVERY_QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        let mut job = queue.remove(key)?;
+        job.attempts += 1;
+        job.next_attempt_at = now + delivery_backoff_ms(job.attempts);
+        queue.insert(delivery_key(&job), job.clone());
+        Some(job)
+    })
+}
+
+async fn deliver_due_notifications() {
+    let config = read_config();
+    let Some(relay_url) = config.delivery_relay_url else { return };
+    let max_attempts = config.delivery_max_attempts.unwrap_or(DEFAULT_DELIVERY_MAX_ATTEMPTS).max(1);
+    let now = get_current_time();
+    
+    let due: Vec<(u64, String)> = DELIVERY_QUEUE.with(|queue| {
+        queue.borrow().range(..(now.max(0) as u64 + 1, String::new()))
+            .take(DELIVERY_BATCH_SIZE)
+            .map(|(key, _)| key)
+            .collect()
+    });
+    
+    for key in due {
+        let notification = NOTIFICATIONS.with(|notifications| notifications.borrow().get(&key.1));
+        let Some(notification) = notification else {
+            // Deleted by the recipient in the meantime
+            DELIVERY_QUEUE.with(|queue| queue.borrow_mut().remove(&key));
+            continue;
+        };
+        let Some(mut job) = claim_delivery(&key, get_current_time()) else {
+            continue;
+        };
+        
+        let result = send_delivery(&relay_url, config.delivery_hmac_secret.as_deref(), &notification).await;
+        let retry_key = delivery_key(&job);
+        match result {
+            Ok(()) => {
+                DELIVERY_QUEUE.with(|queue| queue.borrow_mut().remove(&retry_key));
+                let stored = NOTIFICATIONS.with(|notifications| notifications.borrow().get(&job.notification_id));
+                if let Some(mut notification) = stored {
+                    notification.delivered_at = Some(get_current_time());
+                    save_notification(notification);
+                }
+            }
+            Err(error) => {
+                job.last_error = Some(error);
+                if job.attempts >= max_attempts {
+                    DELIVERY_QUEUE.with(|queue| queue.borrow_mut().remove(&retry_key));
+                    DEAD_LETTERS.with(|dead_letters| {
+                        dead_letters.borrow_mut().insert(job.notification_id.clone(), job)
+                    });
+                } else {
+                    // Already queued for the retry; this records the error
+                    enqueue_delivery(job);
+                }
+            }
+        }
+    }
+}
+
+// Clears the running flag even if a delivery callback traps and its future is dropped
+struct DeliveryRunGuard;
+
+impl Drop for DeliveryRunGuard {
+    fn drop(&mut self) {
+        DELIVERY_RUNNING.with(|running| running.set(false));
+    }
+}
+
+fn run_delivery_queue() {
+    if DELIVERY_RUNNING.with(|running| running.replace(true)) {
+        return;
+    }
+    ic_cdk::spawn(async {
+        let _guard = DeliveryRunGuard;
+        deliver_due_notifications().await;
+    });
+}
+
+// Timers do not survive upgrades, so this runs from both init and post_upgrade
+fn start_notification_maintenance() {
+    ic_cdk_timers::set_timer_interval(
+        std::time::Duration::from_secs(NOTIFICATION_MAINTENANCE_INTERVAL_SECS),
+        run_notification_maintenance,
+    );
+}
+
+fn start_delivery_worker() {
+    let interval = read_config().delivery_interval_secs.unwrap_or(DEFAULT_DELIVERY_INTERVAL_SECS).max(1);
+    ic_cdk_timers::set_timer_interval(std::time::Duration::from_secs(interval), run_delivery_queue);
+}
+
+// Notification Maintenance
+fn notification_retention() -> NotificationRetention {
+    let config = read_config();
+    NotificationRetention {
+        retention_days: config.notification_retention_days.unwrap_or(DEFAULT_NOTIFICATION_RETENTION_DAYS),
+        max_per_user: config.max_notifications_per_user.unwrap_or(DEFAULT_MAX_NOTIFICATIONS_PER_USER),
+    }
+}
+
+// Deletes read notifications past the retention window and, per user, the
+// oldest notifications beyond the cap. The inbox index is ordered by user and
+// newest first, so one pass finds both.
+fn enforce_notification_retention() {
+    let retention = notification_retention();
+    let cutoff = get_current_time() - retention.retention_days as i64 * 86_400_000;
+    
+    let expired: Vec<NotificationKey> = NOTIFICATION_INDEX.with(|index| {
+        let mut expired = Vec::new();
+        let mut current_user: Option<UserId> = None;
+        let mut position = 0u32;
+        for (key, is_read) in index.borrow().iter() {
+            if current_user.as_ref() != Some(&key.0) {
+                current_user = Some(key.0.clone());
+                position = 0;
+            }
+            position += 1;
+            
+            let Reverse(created_at) = key.1;
+            if position > retention.max_per_user || (is_read && (created_at as i64) < cutoff) {
+                expired.push(key);
+                if expired.len() >= MAX_RETENTION_DELETES_PER_RUN {
+                    break;
+                }
+            }
+        }
+        expired
+    });
+    
+    for key in expired {
+        NOTIFICATION_INDEX.with(|index| index.borrow_mut().remove(&key));
+        NOTIFICATIONS.with(|notifications| notifications.borrow_mut().remove(&key.2));
+    }
+}
+
+// Sends each user whose oldest held ShiftPosted event is a day old a single
+// summary of everything held for them. Entries are ordered by user and age,
+// so only each user's oldest entry is read unless the digest is due.
+fn flush_due_digests() {
+    let due_before = (get_current_time() - DIGEST_PERIOD_MS).max(0) as u64;
+    let mut from = (String::new(), 0, String::new());
+    loop {
+        let oldest = DIGEST_QUEUE.with(|queue| queue.borrow().range(from.clone()..).next());
+        let Some(((user_id, created_at, _), _)) = oldest else { break };
+        // No entry is stamped u64::MAX, so this is past all of the user's entries
+        from = (user_id.clone(), u64::MAX, String::new());
+        if created_at > due_before {
+            continue;
+        }
+        
+        let entries: Vec<DigestEntry> = DIGEST_QUEUE.with(|queue| {
+            let mut queue = queue.borrow_mut();
+            let held: Vec<((UserId, u64, String), DigestEntry)> = queue.range((user_id.clone(), 0, String::new())..)
+                .take_while(|((owner, _, _), _)| *owner == user_id)
+                .collect();
+            held.into_iter()
+                .map(|(key, entry)| {
+                    queue.remove(&key);
+                    entry
+                })
+                .collect()
+        });
+        
+        let related_shift_id = match entries.as_slice() {
+            [only] => only.shift_id.clone(),
+            _ => None,
+        };
+        
+        store_notification(
+            &user_id,
+            &notification_preferences(&user_id),
+            NotificationType::ShiftPosted,
+            &format!("{} new shifts posted", entries.len()),
+            digest_message(&entries),
+            related_shift_id,
+        );
+    }
+}
+
+// One line per held event up to MAX_DIGEST_LINES, which always fits the
+// message budget
+fn digest_message(entries: &[DigestEntry]) -> String {
+    let mut lines: Vec<String> = entries.iter()
+        .take(MAX_DIGEST_LINES)
+        .map(|entry| format!("- {}", truncate_text(&entry.summary, MAX_DIGEST_LINE_BYTES)))
+        .collect();
+    if entries.len() > MAX_DIGEST_LINES {
+        lines.push(format!("...and {} more", entries.len() - MAX_DIGEST_LINES));
+    }
+    lines.join("\n")
+}
+
+fn run_notification_maintenance() {
+    enforce_notification_retention();
+    flush_due_digests();
+}
+
+// Shift Sweep
+// Runs on a timer: expires unfilled shifts once their start (plus grace) has
+// passed, starts approved shifts at their start time and flags assigned
+// workers who have not checked out after the end (plus grace).
+fn sweep_shifts() {
+    let config = read_config();
+    let expiry_grace_ms = config.expiry_grace_minutes.unwrap_or(DEFAULT_EXPIRY_GRACE_MINUTES) as i64 * 60_000;
+    let checkout_grace_ms = config.checkout_grace_minutes.unwrap_or(DEFAULT_CHECKOUT_GRACE_MINUTES) as i64 * 60_000;
+    let now = get_current_time();
+    
+    let candidates: Vec<Shift> = [ShiftStatus::Open, ShiftStatus::Claimed, ShiftStatus::Approved, ShiftStatus::InProgress]
+        .iter()
+        .flat_map(shifts_with_status)
+        .collect();
+    
+    let mut no_shows = Vec::new();
+    for mut shift in candidates {
+        let (start, end) = (shift.starts_at, shift.ends_at);
+        
+        match shift.status {
+            ShiftStatus::Open | ShiftStatus::Claimed if shift.seats.is_empty() && now >= start + expiry_grace_ms => {
+                if transition_shift(&mut shift, ShiftStatus::Expired).is_err() {
+                    continue;
+                }
+                push_notification(
+                    &shift.business_id,
+                    NotificationType::ShiftExpired,
+                    "Shift expired",
+                    format!("Your {} shift on {} started without an approved worker", shift.role, shift_local_date(&shift)),
+                    Some(shift.id.clone()),
+                );
+            }
+            // Partially staffed shifts start with the seats they have
+            ShiftStatus::Open | ShiftStatus::Claimed | ShiftStatus::Approved if !shift.seats.is_empty() && now >= start => {
+                if transition_shift(&mut shift, ShiftStatus::InProgress).is_err() {
+                    continue;
+                }
+                for worker_id in seated_workers(&shift) {
+                    push_notification(
+                        &worker_id,
+                        NotificationType::ShiftStarted,
+                        "Shift started",
+                        format!("Your {} shift has started; remember to check in", shift.role),
+                        Some(shift.id.clone()),
+                    );
+                }
+                push_notification(
+                    &shift.business_id,
+                    NotificationType::ShiftStarted,
+                    "Shift started",
+                    format!("The {} shift on {} is now in progress", shift.role, shift_local_date(&shift)),
+                    Some(shift.id.clone()),
+                );
+            }
+            // Nobody checked in by the end of the check-out grace: cancel and refund
+            ShiftStatus::InProgress if now >= end + checkout_grace_ms
+                && shift.seats.iter().all(|seat| seat.status == SeatStatus::Assigned) =>
+            {
+                if transition_shift(&mut shift, ShiftStatus::Cancelled).is_err() {
+                    continue;
+                }
+                notify_shift_cancelled(&shift, &seated_workers(&shift));
+                push_notification(
+                    &shift.business_id,
+                    NotificationType::ShiftCancelled,
+                    "Shift cancelled",
+                    format!("Nobody checked in to the {} shift on {}; it was cancelled and escrow refunded", shift.role, shift_local_date(&shift)),
+                    Some(shift.id.clone()),
+                );
+                no_shows.push(shift.id.clone());
+            }
+            ShiftStatus::InProgress if now >= end + checkout_grace_ms => {
+                let mut overdue = Vec::new();
+                for seat in shift.seats.iter_mut() {
+                    if seat.status == SeatStatus::CheckedIn && seat.checkout_overdue_at.is_none() {
+                        seat.checkout_overdue_at = Some(now);
+                        overdue.push(seat.worker_id.clone());
+                    }
+                }
+                if overdue.is_empty() {
+                    continue;
+                }
+                shift.updated_at = now;
+                for worker_id in &overdue {
+                    push_notification(
+                        worker_id,
+                        NotificationType::CheckOutOverdue,
+                        "Check-out overdue",
+                        format!("Your {} shift ended; please check out", shift.role),
+                        Some(shift.id.clone()),
+                    );
+                }
+                push_notification(
+                    &shift.business_id,
+                    NotificationType::CheckOutOverdue,
+                    "Check-out overdue",
+                    format!("{} worker(s) on the {} shift on {} have not checked out", overdue.len(), shift.role, shift_local_date(&shift)),
+                    Some(shift.id.clone()),
+                );
+            }
+            _ => continue,
+        }
+        
+        save_shift(&shift);
+    }
+    
+    if !no_shows.is_empty() {
+        ic_cdk::spawn(async move {
+            for shift_id in no_shows {
+                settle_shift_escrows_after_change(&shift_id).await;
+            }
+        });
+    }
+}
+
+// Timers do not survive upgrades, so this runs from both init and post_upgrade
+fn start_shift_sweep() {
+    let interval = read_config().sweep_interval_secs.unwrap_or(DEFAULT_SWEEP_INTERVAL_SECS).max(1);
+    ic_cdk_timers::set_timer_interval(std::time::Duration::from_secs(interval), sweep_shifts);
+}
+
+// Canister initialization
+fn apply_init_args(args: Option<InitArgs>) {
+    let Some(args) = args else { return };
+    update_config(|config| {
+        if let Some(ledger) = args.ledger_canister_id {
+            config.ledger_canister_id = Some(ledger);
+        }
+        if let Some(decimals) = args.ledger_decimals {
+            config.ledger_decimals = Some(decimals);
+        }
+        if let Some(currency) = args.default_currency.filter(|c| is_valid_currency(c)) {
+            config.default_currency = Some(currency);
+        }
+        if let Some(percent) = args.advance_cap_percent {
+            config.advance_cap_percent = Some(percent.min(100));
+        }
+        if let Some(bps) = args.advance_fee_bps {
+            config.advance_fee_bps = Some(bps);
+        }
+        if let Some(interval) = args.sweep_interval_secs {
+            config.sweep_interval_secs = Some(interval);
+        }
+        if let Some(minutes) = args.expiry_grace_minutes {
+            config.expiry_grace_minutes = Some(minutes);
+        }
+        if let Some(minutes) = args.checkout_grace_minutes {
+            config.checkout_grace_minutes = Some(minutes);
+        }
+        if let Some(minutes) = args.checkin_grace_minutes {
+            config.checkin_grace_minutes = Some(minutes);
+        }
+        if let Some(minutes) = args.min_shift_gap_minutes {
+            config.min_shift_gap_minutes = Some(minutes);
+        }
+        if let Some(url) = args.delivery_relay_url.filter(|url| url.starts_with("https://")) {
+            config.delivery_relay_url = Some(url);
+        }
+        if let Some(secret) = args.delivery_hmac_secret {
+            config.delivery_hmac_secret = Some(secret);
+        }
+        if let Some(interval) = args.delivery_interval_secs {
+            config.delivery_interval_secs = Some(interval);
+        }
+        if let Some(attempts) = args.delivery_max_attempts {
+            config.delivery_max_attempts = Some(attempts);
+        }
+        if let Some(key_name) = args.ecdsa_key_name {
+            // A different key invalidates the cached public key
+            if key_name != config.ecdsa_key_name {
+                config.ecdsa_public_key = None;
+            }
+            config.ecdsa_key_name = key_name;
+        }
+    });
+}
+
+// Bump when an upgrade needs a new one-off migration in run_migrations
+const SCHEMA_VERSION: u32 = 3;
+
+fn set_schema_version(version: u32) {
+    STORED_SCHEMA_VERSION.with(|cell| {
+        cell.borrow_mut().set(version).expect("Failed to store schema version")
+    });
+}
+
+// Runs each migration once, so upgrades stop rescanning every record
+fn run_migrations() {
+    let from = STORED_SCHEMA_VERSION.with(|cell| *cell.borrow().get());
+    if from < 1 {
+        migrate_legacy_records();
+        if indexes_missing() {
+            rebuild_indexes();
+        }
+        register_existing_credentials();
+        index_existing_notifications();
+        index_user_principals();
+    }
+    if from < 2 {
+        index_worker_profiles();
+    }
+    if from < 3 {
+        index_schedules();
+    }
+    if from < SCHEMA_VERSION {
+        set_schema_version(SCHEMA_VERSION);
+    }
+}
+
+#[ic_cdk::init]
+fn init(args: Option<InitArgs>) {
+    // Nothing stored yet, so there is nothing to migrate
+    set_schema_version(SCHEMA_VERSION);
+    apply_init_args(args);
+    start_shift_sweep();
+    start_delivery_worker();
+    start_notification_maintenance();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    apply_init_args(args);
+    run_migrations();
+    start_shift_sweep();
+    start_delivery_worker();
+    start_notification_maintenance();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    thread_local! {
+        pub(super) static NOW: Cell<i64> = const { Cell::new(0) };
+    }
+
+    const ALL_STATUSES: [ShiftStatus; 8] = [
+        ShiftStatus::Draft,
+        ShiftStatus::Open,
+        ShiftStatus::Claimed,
+        ShiftStatus::Approved,
+        ShiftStatus::InProgress,
+        ShiftStatus::Completed,
+        ShiftStatus::Cancelled,
+        ShiftStatus::Expired,
+    ];
+
+    pub(super) fn test_seat(worker_id: &str, status: SeatStatus) -> ShiftSeat {
+        ShiftSeat {
+            worker_id: worker_id.to_string(),
+            status,
+            assigned_at: 0,
+            checked_in_at: None,
+            checked_out_at: None,
+            checkout_overdue_at: None,
+            work_history_id: None,
+        }
+    }
+
+    pub(super) fn test_shift(status: ShiftStatus, seats: Vec<ShiftSeat>) -> Shift {
+        Shift {
+            id: "shift".to_string(),
+            business_id: "business".to_string(),
+            event_id: None,
+            role: "Server".to_string(),
+            starts_at: 10 * 3_600_000,
+            ends_at: 18 * 3_600_000,
+            utc_offset_minutes: 0,
+            time_zone: None,
+            pay_rate: Money { amount_minor: 2000, currency: "USD".to_string() },
+            location: "Berlin".to_string(),
+            coordinates: None,
+            jurisdiction: None,
+            description: None,
+            requirements: Vec::new(),
+            status,
+            headcount: seats.len().max(1) as u32,
+            seats,
+            applicants: Vec::new(),
+            is_urgent: false,
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn transitions_follow_the_table() {
+        for from in ALL_STATUSES {
+            for to in ALL_STATUSES {
+                let mut shift = test_shift(from.clone(), vec![test_seat("worker", SeatStatus::Assigned)]);
+                let listed = SHIFT_TRANSITIONS.iter().any(|(a, b)| *a == from && *b == to);
+                let result = transition_shift(&mut shift, to.clone());
+                assert_eq!(result.is_ok(), listed, "{:?} -> {:?}", from, to);
+                let expected = if listed { to.clone() } else { from.clone() };
+                assert_eq!(shift.status, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn finished_shifts_are_terminal() {
+        for from in [ShiftStatus::Completed, ShiftStatus::Cancelled, ShiftStatus::Expired] {
+            assert!(!SHIFT_TRANSITIONS.iter().any(|(a, _)| *a == from), "{:?} has an exit", from);
+        }
+    }
+
+    #[test]
+    fn staffed_statuses_need_a_seat() {
+        let mut shift = test_shift(ShiftStatus::Open, Vec::new());
+        assert_eq!(
+            transition_shift(&mut shift, ShiftStatus::Approved),
+            Err(ShiftTransitionError::WorkerNotAssigned { to: ShiftStatus::Approved })
+        );
+        assert_eq!(shift.status, ShiftStatus::Open);
+        assert!(transition_shift(&mut shift, ShiftStatus::Cancelled).is_ok());
+    }
+
+    #[test]
+    fn only_pending_applications_can_be_approved() {
+        for (status, approvable) in [
+            (ApplicationStatus::Pending, true),
+            (ApplicationStatus::Rejected, false),
+            (ApplicationStatus::Approved, false),
+        ] {
+            APPLICATIONS.with(|apps| apps.borrow_mut().insert("shift_worker".to_string(), ShiftApplication {
+                shift_id: "shift".to_string(),
+                worker_id: "worker".to_string(),
+                applied_at: 0,
+                message: None,
+                status: status.clone(),
+            }));
+            assert_eq!(approvable_application("shift_worker").is_ok(), approvable, "{:?}", status);
+        }
+        assert_eq!(approvable_application("shift_nobody").unwrap_err(), "Application not found");
+    }
+
+    #[test]
+    fn no_show_shift_can_be_cancelled_and_refunded() {
+        let seats = vec![test_seat("a", SeatStatus::Assigned), test_seat("b", SeatStatus::Assigned)];
+        let mut shift = test_shift(ShiftStatus::InProgress, seats);
+        assert!(seat_earned_pay(&shift, &"a".to_string()).is_err());
+        
+        transition_shift(&mut shift, ShiftStatus::Cancelled).unwrap();
+        assert_eq!(shift.status, ShiftStatus::Cancelled);
+        assert_eq!(seat_earned_pay(&shift, &"a".to_string()), Ok(false));
+        assert_eq!(seat_earned_pay(&shift, &"b".to_string()), Ok(false));
+    }
+
+    #[test]
+    fn started_work_blocks_cancellation() {
+        // Approved covers an early check-in before the start
+        for status in [ShiftStatus::Approved, ShiftStatus::InProgress] {
+            for started in [SeatStatus::CheckedIn, SeatStatus::Completed] {
+                let seats = vec![test_seat("a", started.clone()), test_seat("b", SeatStatus::Assigned)];
+                let mut shift = test_shift(status.clone(), seats);
+                assert_eq!(
+                    transition_shift(&mut shift, ShiftStatus::Cancelled),
+                    Err(ShiftTransitionError::WorkStarted)
+                );
+                assert_eq!(shift.status, status);
+            }
+        }
+    }
+
+    #[test]
+    fn reopening_keeps_checked_in_seats() {
+        let seats = vec![test_seat("a", SeatStatus::CheckedIn), test_seat("b", SeatStatus::Assigned)];
+        let mut shift = test_shift(ShiftStatus::Approved, seats);
+        assert!(release_all_seats(&mut shift).is_err());
+        assert_eq!((shift.status.clone(), shift.seats.len()), (ShiftStatus::Approved, 2));
+
+        let mut shift = test_shift(ShiftStatus::Approved, vec![test_seat("b", SeatStatus::Assigned)]);
+        assert_eq!(release_all_seats(&mut shift), Ok(vec!["b".to_string()]));
+        assert_eq!((shift.status.clone(), shift.seats.len()), (ShiftStatus::Open, 0));
+    }
+
+    #[test]
+    fn completed_shift_pays_completed_seats_and_refunds_no_shows() {
+        let seats = vec![test_seat("a", SeatStatus::Completed), test_seat("b", SeatStatus::Assigned)];
+        let shift = test_shift(ShiftStatus::Completed, seats);
+        assert_eq!(seat_earned_pay(&shift, &"a".to_string()), Ok(true));
+        assert_eq!(seat_earned_pay(&shift, &"b".to_string()), Ok(false));
+    }
+
+    #[test]
+    fn billable_time_is_clamped_to_the_schedule() {
+        let shift = test_shift(ShiftStatus::InProgress, Vec::new());
+        let (start, end, grace) = (shift.starts_at, shift.ends_at, 30 * 60_000);
+        
+        assert_eq!(billable_interval(&shift, start + 5, end - 5, grace), (start + 5, end - 5));
+        // Checking in two days early earns nothing extra
+        assert_eq!(billable_interval(&shift, start - 2 * DAY_MS, end, grace), (start, end));
+        // Overtime counts up to the check-out grace only
+        assert_eq!(billable_interval(&shift, start, end + 10 * 60_000, grace), (start, end + 10 * 60_000));
+        assert_eq!(billable_interval(&shift, start, end + DAY_MS, grace), (start, end + grace));
+        // Checking out before the start is worth nothing
+        let (from, to) = billable_interval(&shift, start - 60_000, start - 30_000, grace);
+        assert_eq!(to - from, 0);
+    }
+
+    fn test_work_history(pay_minor: u64, confirmed: bool) -> WorkHistory {
+        WorkHistory {
+            id: "entry".to_string(),
+            worker_id: "worker".to_string(),
+            business_id: "business".to_string(),
+            shift_id: "shift".to_string(),
+            role: "Server".to_string(),
+            date_worked: "2024-01-01".to_string(),
+            hours_worked: 8.0,
+            minutes_worked: Some(480),
+            pay_earned: Money { amount_minor: pay_minor, currency: "USD".to_string() },
+            business_name: "Cafe".to_string(),
+            location: "Berlin".to_string(),
+            completed_at: 0,
+            verification_hash: String::new(),
+            confirmed_at: confirmed.then_some(1),
+            signature: None,
+            settled_at: None,
+        }
+    }
+
+    #[test]
+    fn work_history_hashes_whole_minutes() {
+        let mut entry = test_work_history(16_000, false);
+        let canonical = canonical_work_history(&entry);
+        assert!(canonical.contains(r#""location":"Berlin","minutes_worked":480,"pay_earned""#), "{}", canonical);
+        assert!(!canonical.contains("hours_worked"));
+
+        entry.minutes_worked = None;
+        entry.hours_worked = 7.9;
+        let legacy = canonical_work_history(&entry);
+        assert!(legacy.contains(r#""date_worked":"2024-01-01","hours_worked":7.9"#), "{}", legacy);
+        assert!(!legacy.contains("minutes_worked"));
+    }
+
+    #[test]
+    fn advances_need_confirmed_work_within_escrow() {
+        assert_eq!(advanceable_earnings(&test_work_history(16_000, false), None), 0);
+        assert_eq!(advanceable_earnings(&test_work_history(16_000, false), Some(16_000)), 0);
+        assert_eq!(advanceable_earnings(&test_work_history(16_000, true), None), 16_000);
+        // Pay inflated past what the business escrowed only counts up to the escrow
+        assert_eq!(advanceable_earnings(&test_work_history(90_000, true), Some(16_000)), 16_000);
+        assert_eq!(advanceable_earnings(&test_work_history(12_000, true), Some(16_000)), 12_000);
+    }
+
+    #[test]
+    fn funded_seats_block_unescrowed_raises() {
+        let mut shift = test_shift(ShiftStatus::Approved, vec![test_seat("worker", SeatStatus::Assigned)]);
+        shift.id = "escrowed-shift".to_string();
+        let funded = to_ledger_units(&shift.pay_rate.for_duration_ms(shift.ends_at - shift.starts_at));
+        save_escrow(&Escrow {
+            shift_id: shift.id.clone(),
+            business_id: shift.business_id.clone(),
+            business_principal: Principal::anonymous(),
+            worker_id: "worker".to_string(),
+            subaccount: Vec::new(),
+            amount: funded,
+            status: EscrowStatus::Funded,
+            funding_block: None,
+            released_amount: 0,
+            release_block: None,
+            refunded_amount: 0,
+            refund_block: None,
+            created_at: 0,
+            settled_at: None,
+            last_error: None,
+            advance_repayment: None,
+            repayment_block: None,
+        });
+        assert!(check_escrow_covers(&shift).is_ok());
+        
+        let mut longer = shift.clone();
+        longer.ends_at += 3_600_000;
+        assert!(check_escrow_covers(&longer).is_err());
+        
+        let mut raised = shift.clone();
+        raised.pay_rate.amount_minor += 100;
+        assert!(check_escrow_covers(&raised).is_err());
+        
+        let mut shorter = shift.clone();
+        shorter.ends_at -= 3_600_000;
+        assert!(check_escrow_covers(&shorter).is_ok());
+    }
+
+    #[test]
+    fn transition_stamps_updated_at() {
+        NOW.with(|now| now.set(42));
+        let mut shift = test_shift(ShiftStatus::Draft, Vec::new());
+        transition_shift(&mut shift, ShiftStatus::Open).unwrap();
+        assert_eq!(shift.updated_at, 42);
+    }
+
+    #[test]
+    fn out_of_range_offsets_are_clamped_on_read() {
+        let mut shift = test_shift(ShiftStatus::Open, Vec::new());
+        shift.utc_offset_minutes = 120;
+        assert_eq!(shift_local_date(&shift), "1970-01-01");
+        assert!(validate_shift_schedule(&shift).is_ok());
+
+        // Older layouts were never validated; they read as +/-18 hours
+        shift.utc_offset_minutes = i16::MAX;
+        assert_eq!(shift_local_date(&shift), "1970-01-02");
+        assert_eq!(local_day_start(&shift), DAY_MS - 18 * 3_600_000);
+        shift.utc_offset_minutes = i16::MIN;
+        assert_eq!(shift_local_date(&shift), "1969-12-31");
+        assert!(validate_shift_schedule(&shift).is_err());
+
+        shift.starts_at = i64::MAX;
+        assert_eq!(shift_local_date(&shift), "");
+    }
+
+    #[test]
+    fn index_maps_store_string_tuples_in_tuple_order() {
+        NOTIFICATION_INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            index.insert(("bob".to_string(), Reverse(5), "b5".to_string()), false);
+            index.insert(("alice".to_string(), Reverse(1), "a1".to_string()), true);
+            index.insert(("alice".to_string(), Reverse(9), "a9".to_string()), false);
+            index.insert(("alice".to_string(), Reverse(3), "a3".to_string()), false);
+            index.remove(&("alice".to_string(), Reverse(3), "a3".to_string()));
+        });
+        let inbox: Vec<(String, bool)> = NOTIFICATION_INDEX.with(|index| {
+            index.borrow().range(("alice".to_string(), Reverse(u64::MAX), String::new())..)
+                .take_while(|((owner, _, _), _)| owner == "alice")
+                .map(|((_, _, id), is_read)| (id, is_read))
+                .collect()
+        });
+        assert_eq!(inbox, vec![("a9".to_string(), false), ("a1".to_string(), true)]);
+    }
+
+    fn test_worker(id: u8, location: &str, home_area: Option<GeoPoint>) {
+        let worker_id = format!("worker-{}", id);
+        save_user_profile(&UserProfile {
+            id: worker_id.clone(),
+            owner_principal: Principal::from_slice(&[id]),
+            user_type: UserType::Worker,
+            name: worker_id.clone(),
+            email: String::new(),
+            phone: None,
+            location: location.to_string(),
+            created_at: 0,
+            updated_at: 0,
+            did_document: None,
+        });
+        save_worker_profile(&WorkerProfile {
+            user_id: worker_id,
+            skills: Vec::new(),
+            experience_level: String::new(),
+            availability: Vec::new(),
+            bio: None,
+            total_shifts_completed: 0,
+            average_rating: None,
+            is_verified: false,
+            home_area,
+            birth_date: None,
+        });
+    }
+
+    #[test]
+    fn shift_alerts_reach_nearby_and_local_workers_nearest_first() {
+        let point = |latitude, longitude| GeoPoint { latitude, longitude };
+        test_worker(1, "Potsdam", Some(point(52.52, 13.45)));
+        test_worker(2, "Potsdam", Some(point(52.52, 13.41)));
+        test_worker(3, " berlin ", None);
+        test_worker(4, "Munich", Some(point(48.14, 11.58)));
+        // Moving away drops the worker from the old location's index
+        test_worker(5, "Berlin", None);
+        test_worker(5, "Hamburg", None);
+
+        let mut shift = test_shift(ShiftStatus::Open, Vec::new());
+        shift.coordinates = Some(point(52.52, 13.40));
+        assert_eq!(shift_posted_recipients(&shift), vec!["worker-2", "worker-1", "worker-3"]);
+    }
+
+    #[test]
+    fn pay_for_duration_rounds_to_the_nearest_minor_unit() {
+        let rate = Money { amount_minor: 2550, currency: "USD".to_string() };
+        assert_eq!(rate.for_duration_ms(8 * 3_600_000).amount_minor, 20_400);
+        // 25.50 an hour for one minute is 0.425, for 90 seconds 0.6375
+        assert_eq!(rate.for_duration_ms(60_000).amount_minor, 43);
+        assert_eq!(rate.for_duration_ms(90_000).amount_minor, 64);
+        assert_eq!(rate.for_duration_ms(0).amount_minor, 0);
+        assert_eq!(rate.for_duration_ms(-3_600_000).amount_minor, 0);
+        assert_eq!(rate.for_duration_ms(60_000).currency, "USD");
+
+        let yen = Money { amount_minor: 1000, currency: "JPY".to_string() };
+        assert_eq!(yen.for_duration_ms(1_800_000).amount_minor, 500);
+        assert_eq!(Money::from_major(25.5, "USD").amount_minor, 2550);
+        assert_eq!(Money::from_major(1000.0, "JPY").amount_minor, 1000);
+    }
+
+    fn page_ids(items: &[(i128, &str)], page: &PageRequest) -> (Vec<String>, Option<String>) {
+        let items: Vec<(i128, String)> = items.iter().map(|(value, id)| (*value, id.to_string())).collect();
+        let page = paginate(items, page, SortDirection::Asc, |item| item.clone()).unwrap();
+        (page.items.into_iter().map(|(_, id)| id).collect(), page.next_cursor)
+    }
+
+    #[test]
+    fn cursors_resume_after_the_last_item_in_either_direction() {
+        // "b" and "c" tie on the sort value, so the id decides
+        let items = [(30, "d"), (10, "a"), (20, "c"), (20, "b")];
+        let mut page = PageRequest { cursor: None, limit: Some(2), sort_by: None, direction: None };
+
+        assert_eq!(page_ids(&items, &page), (vec!["a".to_string(), "b".to_string()], Some("20:b".to_string())));
+        page.cursor = Some("20:b".to_string());
+        assert_eq!(page_ids(&items, &page), (vec!["c".to_string(), "d".to_string()], None));
+
+        page.direction = Some(SortDirection::Desc);
+        page.cursor = None;
+        assert_eq!(page_ids(&items, &page), (vec!["d".to_string(), "c".to_string()], Some("20:c".to_string())));
+        page.cursor = Some("20:c".to_string());
+        assert_eq!(page_ids(&items, &page), (vec!["b".to_string(), "a".to_string()], None));
+
+        // An item added before the cursor does not shift the next page
+        let grown = [(30, "d"), (10, "a"), (20, "c"), (20, "b"), (25, "e")];
+        assert_eq!(page_ids(&grown, &page), (vec!["b".to_string(), "a".to_string()], None));
+    }
+
+    #[test]
+    fn page_limits_and_bad_cursors() {
+        let items: Vec<(i128, String)> = (0..150).map(|i| (i, format!("{:03}", i))).collect();
+        let mut page = PageRequest { cursor: None, limit: None, sort_by: None, direction: None };
+        let first = paginate(items.clone(), &page, SortDirection::Asc, |item| item.clone()).unwrap();
+        assert_eq!((first.items.len(), first.total), (DEFAULT_PAGE_SIZE, Some(150)));
+        page.limit = Some(1000);
+        assert_eq!(paginate(items.clone(), &page, SortDirection::Asc, |item| item.clone()).unwrap().items.len(), MAX_PAGE_SIZE);
+        page.limit = Some(0);
+        assert_eq!(paginate(items.clone(), &page, SortDirection::Asc, |item| item.clone()).unwrap().items.len(), 1);
+
+        for cursor in ["", "no-separator", "x:id"] {
+            page.cursor = Some(cursor.to_string());
+            assert!(paginate(items.clone(), &page, SortDirection::Asc, |item| item.clone()).is_err(), "{:?}", cursor);
+        }
+    }
+
+    #[test]
+    fn search_matches_role_and_location_ignoring_case() {
+        let shift = test_shift(ShiftStatus::Open, Vec::new());
+        let matches = |location: Option<&str>, role: Option<&str>| {
+            let criteria = ShiftSearchCriteria {
+                location: location.map(str::to_string),
+                role: role.map(str::to_string),
+                ..Default::default()
+            };
+            shift_search_filter(&criteria).unwrap()(&shift)
+        };
+        assert!(matches(Some(" BERLIN "), Some("server")));
+        assert!(matches(Some("erl"), Some(" SERVER")));
+        assert!(!matches(Some("Munich"), None));
+        assert!(!matches(None, Some("Serve")));
+    }
+
+    #[test]
+    fn geohash_matches_reference_values() {
+        let point = |latitude, longitude| GeoPoint { latitude, longitude };
+        assert_eq!(geohash(&point(57.64911, 10.40744), 11), "u4pruydqqvj");
+        assert_eq!(geohash(&point(42.6, -5.6), 5), "ezs42");
+        assert_eq!(geohash(&point(-90.0, -180.0), 6), "000000");
+        assert_eq!(geohash(&point(90.0, 180.0), 6), "zzzzzz");
+        let (width, height) = geohash_cell_size(GEOHASH_PRECISION);
+        assert!((width - 0.010986328125).abs() < 1e-12 && (height - 0.0054931640625).abs() < 1e-12);
+    }
+
+    #[test]
+    fn geohash_cells_cover_the_search_circle() {
+        let point = |latitude, longitude| GeoPoint { latitude, longitude };
+        // Berlin, near the pole, and across the antimeridian
+        for (center, radius_km) in [
+            (point(52.52, 13.40), 10.0),
+            (point(52.52, 13.40), MAX_SEARCH_RADIUS_KM),
+            (point(89.9, 0.0), 50.0),
+            (point(-17.7, 179.99), 20.0),
+        ] {
+            let cells = geohash_cells(&center, radius_km);
+            assert!(!cells.is_empty() && cells.len() <= MAX_GEO_CELLS, "{} cells", cells.len());
+            // Points on the circle, every 15 degrees of bearing
+            for step in 0..24 {
+                let bearing = (step as f64 * 15.0).to_radians();
+                let angle = radius_km / EARTH_RADIUS_KM * 0.999;
+                let latitude = center.latitude.to_radians();
+                let edge_latitude = (latitude.sin() * angle.cos() + latitude.cos() * angle.sin() * bearing.cos()).asin();
+                let edge_longitude = center.longitude.to_radians()
+                    + (bearing.sin() * angle.sin() * latitude.cos()).atan2(angle.cos() - latitude.sin() * edge_latitude.sin());
+                let edge = point(edge_latitude.to_degrees(), (edge_longitude.to_degrees() + 540.0).rem_euclid(360.0) - 180.0);
+                assert!(distance_km(&center, &edge) <= radius_km);
+                let hash = geohash(&edge, GEOHASH_PRECISION);
+                assert!(cells.iter().any(|cell| hash.starts_with(cell.as_str())), "{} not covered around {:?}", hash, center);
+            }
+        }
+    }
+
+    #[test]
+    fn distance_is_great_circle() {
+        let berlin = GeoPoint { latitude: 52.5200, longitude: 13.4050 };
+        let paris = GeoPoint { latitude: 48.8566, longitude: 2.3522 };
+        assert!((distance_km(&berlin, &paris) - 878.0).abs() < 5.0);
+        assert_eq!(distance_km(&berlin, &berlin), 0.0);
+    }
+
+    #[test]
+    fn quiet_hours_end_at_the_next_window_close() {
+        const HOUR: i64 = 3_600_000;
+        const DAY: i64 = 24 * HOUR;
+        let overnight = QuietHours { start_minute: 22 * 60, end_minute: 7 * 60, utc_offset_minutes: 0 };
+        assert_eq!(quiet_hours_end(&overnight, 23 * HOUR + 30 * 60_000 + 45_000), Some(DAY + 7 * HOUR));
+        assert_eq!(quiet_hours_end(&overnight, DAY + 3 * HOUR), Some(DAY + 7 * HOUR));
+        assert_eq!(quiet_hours_end(&overnight, 22 * HOUR), Some(DAY + 7 * HOUR));
+        assert_eq!(quiet_hours_end(&overnight, 7 * HOUR), None);
+        assert_eq!(quiet_hours_end(&overnight, 12 * HOUR), None);
+
+        let lunch = QuietHours { start_minute: 13 * 60, end_minute: 14 * 60, utc_offset_minutes: 0 };
+        assert_eq!(quiet_hours_end(&lunch, 13 * HOUR + 30 * 60_000), Some(14 * HOUR));
+        assert_eq!(quiet_hours_end(&lunch, 14 * HOUR), None);
+
+        // 21:00 UTC is 23:00 at UTC+2, and 07:00 there is 05:00 UTC
+        let eastern = QuietHours { utc_offset_minutes: 120, ..overnight.clone() };
+        assert_eq!(quiet_hours_end(&eastern, 21 * HOUR), Some(DAY + 5 * HOUR));
+        assert_eq!(quiet_hours_end(&eastern, 19 * HOUR), None);
+        let western = QuietHours { utc_offset_minutes: -300, ..overnight.clone() };
+        assert_eq!(quiet_hours_end(&western, DAY + 4 * HOUR), Some(DAY + 12 * HOUR));
+
+        let empty = QuietHours { start_minute: 600, end_minute: 600, utc_offset_minutes: 0 };
+        assert_eq!(quiet_hours_end(&empty, 10 * HOUR), None);
+    }
+
+    #[test]
+    fn hmac_sha256_matches_rfc_4231() {
+        let large_key = [0xaa; 131];
+        let cases: [(&[u8], &[u8], &str); 6] = [
+            (&[0x0b; 20], b"Hi There", "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+            (b"Jefe", b"what do ya want for nothing?", "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+            (&[0xaa; 20], &[0xdd; 50], "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"),
+            (
+                &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25],
+                &[0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            (
+                &large_key,
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                &large_key,
+                b"This is a test using a larger than block-size key and a larger than block-size data. \
+                  The key needs to be hashed before being used by the HMAC algorithm.",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+        for (case, (key, message, expected)) in [1, 2, 3, 4, 6, 7].into_iter().zip(cases) {
+            assert_eq!(to_hex(&hmac_sha256(key, message)), expected, "test case {}", case);
+        }
+        // Test case 5 only specifies the first 128 bits
+        assert!(to_hex(&hmac_sha256(&[0x0c; 20], b"Test With Truncation")).starts_with("a3b6167473100ee06e0c796c2955552b"));
+    }
+
+    #[test]
+    fn notifications_at_the_text_budget_fit_their_bound() {
+        use ic_stable_structures::Storable;
+        let long = "€".repeat(1_000);
+        let id = "x".repeat(64);
+        let summary = truncate_text(&long, MAX_DIGEST_LINE_BYTES);
+        assert!(summary.len() <= MAX_DIGEST_LINE_BYTES && summary.ends_with('…'));
+
+        let entry = DigestEntry { shift_id: Some(id.clone()), summary, created_at: i64::MAX };
+        assert!(entry.to_bytes().len() <= 512);
+        let digest = digest_message(&vec![entry; 10_000]);
+        assert!(digest.len() <= MAX_NOTIFICATION_MESSAGE_BYTES && digest.ends_with("...and 9995 more"));
+
+        let notification = Notification {
+            id: id.clone(),
+            user_id: id.clone(),
+            notification_type: NotificationType::CheckOutOverdue,
+            title: truncate_text(&long, MAX_NOTIFICATION_TITLE_BYTES),
+            message: truncate_text(&long, MAX_NOTIFICATION_MESSAGE_BYTES),
+            related_shift_id: Some(id),
+            is_read: true,
+            created_at: i64::MAX,
+            channel: Some(NotificationChannel::Outbound),
+            deliver_after: Some(i64::MAX),
+            delivered_at: Some(i64::MAX),
+        };
+        assert!(notification.to_bytes().len() <= 1024, "{}", notification.to_bytes().len());
+    }
+
+    #[test]
+    fn claimed_deliveries_stay_queued_until_settled() {
+        let job = DeliveryJob {
+            notification_id: "n1".to_string(),
+            user_id: "worker".to_string(),
+            attempts: 0,
+            next_attempt_at: 100,
+            last_error: None,
+            created_at: 0,
+        };
+        enqueue_delivery(job);
+        let claimed = claim_delivery(&(100, "n1".to_string()), 1_000).unwrap();
+        assert_eq!((claimed.attempts, claimed.next_attempt_at), (1, 1_000 + DELIVERY_BACKOFF_BASE_MS));
+
+        // Were the outcall to trap now, the job would still be there for a retry
+        let queued: Vec<(u64, String)> = DELIVERY_QUEUE.with(|queue| queue.borrow().iter().map(|(key, _)| key).collect());
+        assert_eq!(queued, vec![delivery_key(&claimed)]);
+        assert!(claim_delivery(&(100, "n1".to_string()), 1_000).is_none());
+    }
+
+    fn finding_rules(findings: &[ComplianceFinding]) -> Vec<(&str, RuleSeverity)> {
+        findings.iter().map(|finding| (finding.rule.as_str(), finding.severity)).collect()
+    }
+
+    #[test]
+    fn labor_rules_flag_pay_hours_rest_and_minors() {
+        let entry = |rule, severity| LaborRuleEntry { rule, severity };
+        LABOR_RULES.with(|rules| rules.borrow_mut().insert("DE".to_string(), LaborRuleSet {
+            jurisdiction: "DE".to_string(),
+            rules: vec![
+                entry(LaborRule::MinPayRate { rate: Money { amount_minor: 1250, currency: "EUR".to_string() } }, RuleSeverity::Block),
+                entry(LaborRule::MaxDailyWork { minutes: 600 }, RuleSeverity::Warn),
+                entry(LaborRule::MinRest { minutes: 660 }, RuleSeverity::Block),
+                entry(LaborRule::MinorWorker {
+                    adult_age: 18,
+                    max_daily_minutes: 480,
+                    earliest_start_minute: 6 * 60,
+                    latest_end_minute: 20 * 60,
+                }, RuleSeverity::Block),
+            ],
+            updated_at: 0,
+        }));
+
+        // 10:00-18:00 on 1970-01-01
+        let mut shift = test_shift(ShiftStatus::Open, Vec::new());
+        assert!(evaluate_labor_rules(&shift, None).is_empty(), "no jurisdiction, no rules");
+        shift.jurisdiction = Some("DE".to_string());
+        shift.pay_rate = Money { amount_minor: 1300, currency: "EUR".to_string() };
+        assert!(evaluate_labor_rules(&shift, None).is_empty());
+        shift.pay_rate.amount_minor = 1200;
+        assert_eq!(finding_rules(&evaluate_labor_rules(&shift, None)), vec![("MinPayRate", RuleSeverity::Block)]);
+        shift.pay_rate = Money { amount_minor: 2000, currency: "USD".to_string() };
+        assert_eq!(finding_rules(&evaluate_labor_rules(&shift, None)), vec![("MinPayRate", RuleSeverity::Block)]);
+        shift.pay_rate = Money { amount_minor: 1300, currency: "EUR".to_string() };
+
+        // A 02:00-06:00 shift the same day: 12 hours in total, 4 hours' rest
+        let worker_id = "worker-7".to_string();
+        let mut earlier = test_shift(ShiftStatus::Approved, vec![test_seat(&worker_id, SeatStatus::Assigned)]);
+        earlier.id = "earlier".to_string();
+        earlier.starts_at = 2 * 3_600_000;
+        earlier.ends_at = 6 * 3_600_000;
+        save_shift(&earlier);
+        assert_eq!(
+            finding_rules(&evaluate_labor_rules(&shift, Some(&worker_id))),
+            vec![("MaxDailyWork", RuleSeverity::Warn), ("MinRest", RuleSeverity::Block)],
+        );
+
+        // The same worker as a nine-year-old, on a shift running past 20:00
+        test_worker(7, "Berlin", None);
+        WORKER_PROFILES.with(|profiles| {
+            let mut profile = profiles.borrow().get(&worker_id).unwrap();
+            profile.birth_date = Some("1960-06-01".to_string());
+            profiles.borrow_mut().insert(worker_id.clone(), profile);
+        });
+        shift.ends_at = 21 * 3_600_000;
+        let findings = evaluate_labor_rules(&shift, Some(&worker_id));
+        assert_eq!(finding_rules(&findings), vec![
+            ("MaxDailyWork", RuleSeverity::Warn),
+            ("MinRest", RuleSeverity::Block),
+            ("MinorWorker", RuleSeverity::Block),
+            ("MinorWorker", RuleSeverity::Block),
+        ]);
+        assert!(findings[2].message.contains("between 06:00 and 20:00"), "{}", findings[2].message);
+    }
+
+    #[test]
+    fn worked_time_counts_whole_minutes() {
+        let mut entry = test_work_history(16_000, true);
+        entry.completed_at = 18 * 3_600_000;
+        entry.hours_worked = 7.999;
+        save_work_history(&entry);
+        let mut other = test_shift(ShiftStatus::Open, Vec::new());
+        other.id = "other".to_string();
+        assert_eq!(worker_intervals(&"worker".to_string(), &other), vec![(10 * 3_600_000, 18 * 3_600_000)]);
+
+        entry.minutes_worked = None;
+        assert_eq!(entry.worked_minutes(), 480);
+    }
+
+    #[test]
+    fn did_canonical_form_signs_whole_numbers() {
+        let document = DIDDocument {
+            worker_id: "worker".to_string(),
+            work_history: vec!["w1".to_string(), "w2".to_string()],
+            ratings: vec!["r1".to_string(), "r2".to_string(), "r3".to_string()],
+            total_shifts: 2,
+            average_rating: Some(14.0 / 3.0),
+            skills_verified: vec!["barista".to_string()],
+            created_at: 1,
+            updated_at: 2,
+            signature: "ignored".to_string(),
+            signed_digest: None,
+        };
+        assert_eq!(
+            canonical_did_document(&document),
+            concat!(
+                r#"{"average_rating_hundredths":467,"created_at":1,"ratings":["r1","r2","r3"],"#,
+                r#""skills_verified":["barista"],"total_shifts":2,"updated_at":2,"#,
+                r#""work_history":["w1","w2"],"worker_id":"worker"}"#,
+            ),
+        );
+        let unrated = DIDDocument { average_rating: None, ..document };
+        assert!(canonical_did_document(&unrated).starts_with(r#"{"average_rating_hundredths":null,"#));
+    }
+
+    #[test]
+    fn work_summaries_disclose_whole_minutes() {
+        save_work_history(&test_work_history(16_000, true));
+        let mut legacy = test_work_history(700, true);
+        legacy.id = "legacy".to_string();
+        legacy.minutes_worked = None;
+        legacy.hours_worked = 0.35;
+        save_work_history(&legacy);
+
+        let criteria = AggregateCriteria {
+            business_type: None,
+            role: None,
+            completed_after: None,
+            completed_before: None,
+            min_average_rating: None,
+        };
+        let claim = aggregate_claim(&"worker".to_string(), &criteria).unwrap();
+        assert_eq!(claim["shiftCount"], json!(2));
+        assert_eq!(claim["totalMinutes"], json!(501));
+        assert!(claim.get("totalHours").is_none());
+    }
+
+    #[test]
+    fn check_in_needs_a_live_shift() {
+        let early = 9 * 3_600_000 + 45 * 60_000;
+        for status in [ShiftStatus::Cancelled, ShiftStatus::Expired, ShiftStatus::Completed] {
+            let mut shift = test_shift(status.clone(), vec![test_seat("worker", SeatStatus::Assigned)]);
+            assert!(check_in_seat(&mut shift, &"worker".to_string(), early, 30).is_err(), "{:?}", status);
+            assert_eq!(shift.seats[0].checked_in_at, None);
+        }
+
+        let mut shift = test_shift(ShiftStatus::Approved, vec![test_seat("worker", SeatStatus::Assigned)]);
+        assert!(check_in_seat(&mut shift, &"worker".to_string(), early - 30 * 60_000, 30).is_err(), "too early");
+        check_in_seat(&mut shift, &"worker".to_string(), early, 30).unwrap();
+        assert_eq!((shift.status.clone(), shift.seats[0].status.clone()), (ShiftStatus::Approved, SeatStatus::CheckedIn));
+
+        // One of two seats filled, checking in at the start
+        let mut partial = test_shift(ShiftStatus::Open, vec![test_seat("worker", SeatStatus::Assigned)]);
+        partial.headcount = 2;
+        check_in_seat(&mut partial, &"worker".to_string(), 10 * 3_600_000, 30).unwrap();
+        assert_eq!(partial.status, ShiftStatus::InProgress);
+    }
+
+    #[test]
+    fn repayments_below_the_fee_stay_with_the_worker() {
+        assert_eq!(split_worker_share(10_000, 2_500, 10), (7_500, 2_500));
+        assert_eq!(split_worker_share(10_000, 10, 10), (10_000, 0));
+        assert_eq!(split_worker_share(10_000, 3, 10), (10_000, 0));
+        assert_eq!(split_worker_share(10_000, 0, 10), (10_000, 0));
+        // Either way nothing is left behind in the escrow beyond the refund
+        for owed in [0, 3, 10, 11, 2_500] {
+            let (payout, repayment) = split_worker_share(10_000, owed, 10);
+            assert_eq!(payout + repayment, 10_000);
+        }
+    }
+
+    #[test]
+    fn user_principals_are_found_by_id() {
+        let principal = Principal::from_slice(&[9]);
+        PRINCIPAL_TO_USERID.with(|mapping| mapping.borrow_mut().insert(principal, "user-9".to_string()));
+        assert_eq!(find_user_principal(&"user-9".to_string()), None);
+        index_user_principals();
+        assert_eq!(find_user_principal(&"user-9".to_string()), Some(principal));
+        assert_eq!(find_user_principal(&"nobody".to_string()), None);
+    }
+
+    #[test]
+    fn advance_history_pages_newest_first_per_worker() {
+        for (id, worker_id, requested_at) in [("a1", "worker", 10), ("a2", "worker", 30), ("b1", "other", 20), ("a3", "worker", 20)] {
+            save_advance(&Advance {
+                id: id.to_string(),
+                worker_id: worker_id.to_string(),
+                amount: Money { amount_minor: 1_000, currency: "USD".to_string() },
+                fee: Money { amount_minor: 10, currency: "USD".to_string() },
+                allocations: Vec::new(),
+                status: AdvanceStatus::Paid,
+                payout_block: None,
+                requested_at,
+                repaid_at: None,
+                last_error: None,
+            });
+        }
+        let ids = |page: Page<Advance>| (page.items.into_iter().map(|advance| advance.id).collect::<Vec<_>>(), page.next_cursor);
+        let worker = "worker".to_string();
+        let mut page = PageRequest { cursor: None, limit: Some(2), sort_by: None, direction: None };
+
+        assert_eq!(ids(advance_history(&worker, &page).unwrap()), (vec!["a2".to_string(), "a3".to_string()], Some("20:a3".to_string())));
+        page.cursor = Some("20:a3".to_string());
+        assert_eq!(ids(advance_history(&worker, &page).unwrap()), (vec!["a1".to_string()], None));
+
+        page.direction = Some(SortDirection::Asc);
+        page.cursor = None;
+        assert_eq!(ids(advance_history(&worker, &page).unwrap()), (vec!["a1".to_string(), "a3".to_string()], Some("20:a3".to_string())));
+        page.cursor = Some("20:a3".to_string());
+        assert_eq!(ids(advance_history(&worker, &page).unwrap()), (vec!["a2".to_string()], None));
+
+        page.cursor = Some("-1:a3".to_string());
+        assert!(advance_history(&worker, &page).is_err());
+        assert_eq!(worker_advances(&"other".to_string()).len(), 1);
+    }
+
+    #[test]
+    fn committed_schedule_pages_in_start_order() {
+        for (id, starts_at, status, seat) in [
+            ("late", 30, ShiftStatus::Approved, SeatStatus::Assigned),
+            ("early", 10, ShiftStatus::InProgress, SeatStatus::CheckedIn),
+            ("middle", 20, ShiftStatus::Open, SeatStatus::Assigned),
+            ("done", 5, ShiftStatus::InProgress, SeatStatus::Completed),
+            ("cancelled", 15, ShiftStatus::Cancelled, SeatStatus::Assigned),
+        ] {
+            let mut shift = test_shift(status, vec![test_seat("worker", seat)]);
+            shift.id = id.to_string();
+            shift.starts_at = starts_at;
+            save_shift(&shift);
+        }
+        let ids = |page: Page<Shift>| (page.items.into_iter().map(|shift| shift.id).collect::<Vec<_>>(), page.next_cursor);
+        let worker = "worker".to_string();
+        let mut page = PageRequest { cursor: None, limit: Some(2), sort_by: None, direction: None };
+
+        assert_eq!(ids(committed_schedule(&worker, &page).unwrap()), (vec!["early".to_string(), "middle".to_string()], Some("20:middle".to_string())));
+        page.cursor = Some("20:middle".to_string());
+        assert_eq!(ids(committed_schedule(&worker, &page).unwrap()), (vec!["late".to_string()], None));
+
+        // Leaving the schedule, or moving within it, updates the index
+        let mut late = SHIFTS.with(|shifts| shifts.borrow().get(&"late".to_string())).unwrap();
+        late.starts_at = 1;
+        save_shift(&late);
+        let mut middle = SHIFTS.with(|shifts| shifts.borrow().get(&"middle".to_string())).unwrap();
+        middle.status = ShiftStatus::Cancelled;
+        save_shift(&middle);
+        let ids: Vec<ShiftId> = committed_shifts(&worker).into_iter().map(|shift| shift.id).collect();
+        assert_eq!(ids, vec!["late".to_string(), "early".to_string()]);
+    }
+
+    #[test]
+    fn compliance_records_page_oldest_first() {
+        for (id, shift_id, recorded_at) in [("r2", "shift", 20), ("r1", "shift", 10), ("x1", "other", 15), ("r3", "shift", 30)] {
+            save_compliance_record(ComplianceRecord {
+                id: id.to_string(),
+                shift_id: shift_id.to_string(),
+                worker_id: None,
+                jurisdiction: "DE".to_string(),
+                action: "assign".to_string(),
+                findings: Vec::new(),
+                recorded_at,
+            });
+        }
+        let shift_id = "shift".to_string();
+        let mut page = PageRequest { cursor: None, limit: Some(2), sort_by: None, direction: None };
+        let first = compliance_records(&shift_id, &page).unwrap();
+        assert_eq!(first.items.iter().map(|record| record.id.as_str()).collect::<Vec<_>>(), vec!["r1", "r2"]);
+        page.cursor = first.next_cursor;
+        let second = compliance_records(&shift_id, &page).unwrap();
+        assert_eq!(second.items.iter().map(|record| record.id.as_str()).collect::<Vec<_>>(), vec!["r3"]);
+        assert_eq!(second.next_cursor, None);
+    }
 }